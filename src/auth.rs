@@ -1,48 +1,54 @@
-// use std::time::{SystemTime, UNIX_EPOCH};
-
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
 use serde_json::json;
+use sha2::{Digest, Sha256};
+use tokio::sync::broadcast;
 
 use crate::{
     client::{Error, LoginResponse, Method, Response, SupabaseClient, UserResponse},
-    // utils::decode_jwt,
+    session::{AuthChange, AuthEvent, Session},
+    utils::{decode_jwt, encode, unix_now},
 };
 
+// refresh this many seconds before the access token actually expires
+const EXPIRY_MARGIN: i64 = 60;
+
 impl SupabaseClient {
-    pub async fn set_session(&mut self, access_token: &str, refresh_token: &str) -> LoginResponse {
-        self.access_token = Some(access_token.to_owned());
-        self.refresh_token = Some(refresh_token.to_owned());
-
-        // let time_now = SystemTime::now()
-        //     .duration_since(UNIX_EPOCH)
-        //     .unwrap()
-        //     .as_secs() as i64;
-
-        // let mut has_expired = true;
-        // if let Ok(claims) = decode_jwt(access_token)
-        //     && let Some(exp) = claims.exp
-        // {
-        //     has_expired = exp <= time_now;
-        // }
-
-        // if !has_expired {
-        //     return LoginResponse{ access_token: access_token.to_owned(), refresh_token:refresh_token.to_owned() }
-        // }
-
-        // match self.refresh_token().await {
-        //     Ok(res) => {
-        //         let data = res.data.unwrap();
-        //         self.access_token = Some(data.access_token);
-        //         self.refresh_token = Some(data.refresh_token);
-        //     }
-        //     Err(e) => {
-        //         dbg!(e);
-        //     }
-        // }
-
-        LoginResponse {
-            access_token: self.access_token.clone().unwrap().to_owned(),
-            refresh_token: self.refresh_token.clone().unwrap().to_owned(),
+    pub fn access_token(&self) -> Option<String> {
+        self.session.access_token()
+    }
+
+    pub fn session(&self) -> Session {
+        self.session.get()
+    }
+
+    pub fn on_auth_state_change(&self) -> broadcast::Receiver<AuthChange> {
+        self.session.subscribe()
+    }
+
+    pub async fn set_session(
+        &self,
+        access_token: &str,
+        refresh_token: &str,
+    ) -> Result<LoginResponse, Error> {
+        self.store_session(
+            &LoginResponse {
+                access_token: access_token.to_owned(),
+                refresh_token: refresh_token.to_owned(),
+                user: None,
+            },
+            AuthEvent::SignedIn,
+        );
+
+        if self.is_expiring() {
+            self.refresh_session().await?;
         }
+
+        let session = self.session.get();
+        Ok(LoginResponse {
+            access_token: session.access_token.unwrap_or_default(),
+            refresh_token: session.refresh_token.unwrap_or_default(),
+            user: session.user,
+        })
     }
 
     pub async fn signup(&self, email: &str, password: &str) -> Result<Response<String>, Error> {
@@ -62,12 +68,14 @@ impl SupabaseClient {
     pub async fn anonymous_login(&self) -> Result<Response<LoginResponse>, Error> {
         let res = self
             .request(Method::POST, "auth/v1/signup", json!({}), None, None)
-            .await?;
+            .await?
+            .json::<LoginResponse>()?;
 
-        Ok(Response {
-            code: res.code,
-            data: serde_json::from_str(&res.data.unwrap()).unwrap(),
-        })
+        if let Some(login) = &res.data {
+            self.store_session(login, AuthEvent::SignedIn);
+        }
+
+        Ok(res)
     }
 
     pub async fn email_login(
@@ -86,59 +94,380 @@ impl SupabaseClient {
                 None,
                 None,
             )
-            .await?;
+            .await?
+            .json::<LoginResponse>()?;
 
-        Ok(Response {
-            code: res.code,
-            data: serde_json::from_str(&res.data.unwrap()).unwrap(),
-        })
+        if let Some(login) = &res.data {
+            self.store_session(login, AuthEvent::SignedIn);
+        }
+
+        Ok(res)
+    }
+
+    // the url to open in a browser for signing in with an oauth provider such
+    // as "github" or "google". the provider redirects to `redirect_to` with a
+    // `code` to pass to `exchange_code_for_session`
+    pub fn sign_in_with_oauth(
+        &self,
+        provider: &str,
+        redirect_to: Option<&str>,
+        scopes: Option<&str>,
+    ) -> Result<String, Error> {
+        let mut random = [0u8; 32];
+        getrandom::fill(&mut random).map_err(|e| Error::Pkce {
+            message: format!("failed to generate code verifier: {}", e),
+        })?;
+
+        let verifier = URL_SAFE_NO_PAD.encode(random);
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+
+        self.session
+            .set_code_verifier(&verifier)
+            .map_err(|e| Error::Pkce {
+                message: format!("failed to store code verifier: {}", e),
+            })?;
+
+        let mut url = format!(
+            "{}/auth/v1/authorize?provider={}",
+            self.base_url,
+            encode(provider)
+        );
+        if let Some(redirect_to) = redirect_to {
+            url += &format!("&redirect_to={}", encode(redirect_to));
+        }
+        if let Some(scopes) = scopes {
+            url += &format!("&scopes={}", encode(scopes));
+        }
+        url += &format!("&code_challenge={}&code_challenge_method=s256", challenge);
+
+        Ok(url)
+    }
+
+    pub async fn exchange_code_for_session(
+        &self,
+        code: &str,
+    ) -> Result<Response<LoginResponse>, Error> {
+        // a verifier is only good for one exchange, whatever its outcome
+        let verifier = self
+            .session
+            .take_code_verifier()
+            .ok_or_else(|| Error::Pkce {
+                message: "no code verifier stored, call sign_in_with_oauth first".to_string(),
+            })?;
+
+        let res = self
+            .request(
+                Method::POST,
+                "auth/v1/token?grant_type=pkce",
+                json!({
+                    "auth_code": code,
+                    "code_verifier": verifier
+                }),
+                None,
+                None,
+            )
+            .await?
+            .json::<LoginResponse>()?;
+
+        if let Some(login) = &res.data {
+            self.store_session(login, AuthEvent::SignedIn);
+        }
+
+        Ok(res)
     }
 
     pub async fn logout(&self) -> Result<Response<String>, Error> {
-        self.request(
-            Method::POST,
-            "auth/v1/logout",
-            json!({}),
-            Some(&self.access_token.clone().unwrap_or("".to_owned())),
-            None,
-        )
-        .await
+        let res = self
+            .authed_request(Method::POST, "auth/v1/logout", json!({}), None)
+            .await;
+
+        // the local session is dropped even if the server rejects the call
+        self.session.clear();
+
+        res
+    }
+
+    pub async fn refresh_session(&self) -> Result<Response<LoginResponse>, Error> {
+        let _guard = self.session.refresh_lock.lock().await;
+        self.refresh_grant().await
     }
 
-    // async fn refresh_token(&self) -> Result<Response<LoginResponse>, Error> {
-    //     let res = self
-    //         .request(
-    //             Method::POST,
-    //             "auth/v1/token",
-    //             json!({
-    //                 "refresh_token": self.refresh_token.clone().unwrap(),
-    //                 "grant_type": "refresh_token"
-    //             }),
-    //             None,
-    //             None,
-    //         )
-    //         .await?;
-
-    //     Ok(Response {
-    //         code: res.code,
-    //         data: serde_json::from_str(&res.data.unwrap()).unwrap(),
-    //     })
-    // }
+    async fn refresh_grant(&self) -> Result<Response<LoginResponse>, Error> {
+        let refresh_token = self.session.refresh_token().unwrap_or_default();
 
-    pub async fn user(&self) -> Result<Response<UserResponse>, Error> {
         let res = self
             .request(
-                Method::GET,
-                "auth/v1/user",
-                json!({}),
-                Some(&self.access_token.clone().unwrap_or("".to_owned())),
+                Method::POST,
+                "auth/v1/token?grant_type=refresh_token",
+                json!({
+                    "refresh_token": refresh_token
+                }),
+                None,
                 None,
             )
             .await?;
 
+        let data: LoginResponse = serde_json::from_str(&res.data.unwrap_or_default())
+            .map_err(|e| Error::decode(res.code, e))?;
+        self.store_session(&data, AuthEvent::TokenRefreshed);
+
         Ok(Response {
             code: res.code,
-            data: serde_json::from_str(&res.data.unwrap()).unwrap(),
+            data: Some(data),
+            count: None,
         })
     }
+
+    // current access token, refreshed first when it is about to expire. a
+    // failed refresh is an error rather than a silent fall back to anon
+    pub(crate) async fn bearer(&self) -> Result<Option<String>, Error> {
+        let token = self.access_token();
+        if token.is_none() || !self.is_expiring() {
+            return Ok(token);
+        }
+        self.refresh_stale(token.as_deref()).await
+    }
+
+    // refreshes unless another clone already replaced the stale token while
+    // we were waiting for the lock
+    pub(crate) async fn refresh_stale(&self, stale: Option<&str>) -> Result<Option<String>, Error> {
+        let _guard = self.session.refresh_lock.lock().await;
+
+        let current = self.access_token();
+        if current.as_deref() != stale {
+            return Ok(current);
+        }
+
+        match self.refresh_grant().await {
+            Ok(_) => Ok(self.access_token()),
+            Err(e) => {
+                // a rejected refresh token can never succeed, so the user is
+                // signed out. network errors keep the session for a retry
+                if let Error::Auth { .. } = e {
+                    self.session.clear();
+                }
+                Err(e)
+            }
+        }
+    }
+
+    pub(crate) fn store_session(&self, login: &LoginResponse, event: AuthEvent) {
+        let expires_at = decode_jwt(&login.access_token)
+            .ok()
+            .and_then(|claims| claims.exp);
+
+        // a refresh response may omit the user, keep the one we already know
+        let user = login.user.clone().or_else(|| match event {
+            AuthEvent::TokenRefreshed => self.session.get().user,
+            _ => None,
+        });
+
+        self.session.set(
+            Session {
+                access_token: Some(login.access_token.clone()),
+                refresh_token: Some(login.refresh_token.clone()),
+                expires_at,
+                user,
+            },
+            event,
+        );
+    }
+
+    fn is_expiring(&self) -> bool {
+        match self.session.expires_at() {
+            Some(exp) => exp - EXPIRY_MARGIN <= unix_now(),
+            None => false,
+        }
+    }
+
+    pub async fn user(&self) -> Result<Response<UserResponse>, Error> {
+        let res = self
+            .authed_request(Method::GET, "auth/v1/user", json!({}), None)
+            .await?
+            .json::<UserResponse>()?;
+
+        if let Some(user) = &res.data {
+            self.session.set_user(user.clone());
+        }
+
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::Duration;
+
+    use super::*;
+    use crate::client::{Body, RequestOptions};
+    use crate::test_server::{Reply, Server, jwt};
+
+    fn token(id: u64, expires_in: i64) -> String {
+        jwt(json!({ "sub": "user", "jti": id, "exp": unix_now() + expires_in }))
+    }
+
+    // valid for decades, so only a 401 can make it refresh
+    fn long_lived() -> String {
+        jwt(json!({ "sub": "user", "jti": 1, "exp": 4_000_000_000i64 }))
+    }
+
+    fn sign_in(client: &SupabaseClient, access_token: &str) {
+        client.store_session(
+            &LoginResponse {
+                access_token: access_token.to_string(),
+                refresh_token: "refresh".to_string(),
+                user: None,
+            },
+            AuthEvent::SignedIn,
+        );
+    }
+
+    // hands out a new token per refresh, and lets `authorized` decide which
+    // bearer tokens the api accepts
+    async fn auth_server(authorized: fn(&str) -> bool) -> Server {
+        let refreshes = AtomicU64::new(0);
+        Server::start(move |req| {
+            if req.path.starts_with("auth/v1/token") {
+                // slow enough for racing clones to pile up on the lock
+                std::thread::sleep(Duration::from_millis(50));
+                let id = refreshes.fetch_add(1, Ordering::SeqCst) + 100;
+                return Reply::json(
+                    200,
+                    json!({ "access_token": token(id, 3600), "refresh_token": "next" }),
+                );
+            }
+
+            let bearer = req
+                .header("authorization")
+                .and_then(|h| h.strip_prefix("Bearer "))
+                .unwrap_or("");
+            if authorized(bearer) {
+                Reply::json(200, json!([]))
+            } else {
+                Reply::json(401, json!({ "message": "JWT expired" }))
+            }
+        })
+        .await
+    }
+
+    async fn get(client: &SupabaseClient) -> Result<reqwest::Response, Error> {
+        client
+            .authed_send(
+                Method::GET,
+                "rest/v1/todos",
+                Body::Empty,
+                &RequestOptions::default(),
+            )
+            .await
+    }
+
+    fn bearers(server: &Server) -> Vec<String> {
+        server
+            .requests()
+            .iter()
+            .filter(|r| r.path.starts_with("rest/v1"))
+            .filter_map(|r| r.header("authorization").map(String::from))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn refreshes_before_expiry() {
+        let server = auth_server(|_| true).await;
+        let client = server.client();
+        let stale = token(1, 10);
+        sign_in(&client, &stale);
+
+        let res = get(&client).await.unwrap();
+
+        assert_eq!(res.status(), 200);
+        assert_eq!(server.count("auth/v1/token"), 1);
+        let fresh = client.access_token().unwrap();
+        assert_ne!(fresh, stale);
+        assert_eq!(bearers(&server), [format!("Bearer {}", fresh)]);
+
+        let refresh = server
+            .requests()
+            .into_iter()
+            .find(|r| r.path.starts_with("auth/v1/token"))
+            .unwrap();
+        assert_eq!(refresh.method, "POST");
+        assert!(refresh.body.contains("\"refresh_token\":\"refresh\""));
+    }
+
+    #[tokio::test]
+    async fn refreshes_and_retries_once_on_401() {
+        // only refreshed tokens are accepted
+        let server = auth_server(|bearer| bearer != long_lived()).await;
+        let client = server.client();
+        sign_in(&client, &long_lived());
+
+        let res = get(&client).await.unwrap();
+
+        assert_eq!(res.status(), 200);
+        assert_eq!(server.count("auth/v1/token"), 1);
+        assert_eq!(server.count("rest/v1"), 2);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_one_retry() {
+        let server = auth_server(|_| false).await;
+        let client = server.client();
+        sign_in(&client, &long_lived());
+
+        let res = get(&client).await.unwrap();
+
+        assert_eq!(res.status(), 401);
+        assert_eq!(server.count("auth/v1/token"), 1);
+        assert_eq!(server.count("rest/v1"), 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn racing_clones_share_one_refresh() {
+        let server = auth_server(|_| true).await;
+        let client = server.client();
+        sign_in(&client, &token(1, 10));
+
+        let tasks = (0..5)
+            .map(|_| {
+                let client = client.clone();
+                tokio::spawn(async move { get(&client).await.map(|r| r.status()) })
+            })
+            .collect::<Vec<_>>();
+        for task in tasks {
+            assert_eq!(task.await.unwrap().unwrap(), 200);
+        }
+
+        assert_eq!(server.count("auth/v1/token"), 1);
+        let fresh = format!("Bearer {}", client.access_token().unwrap());
+        assert!(bearers(&server).iter().all(|b| *b == fresh));
+    }
+
+    #[tokio::test]
+    async fn rejected_refresh_token_signs_out() {
+        let server = Server::start(|req| {
+            if req.path.starts_with("auth/v1/token") {
+                Reply::json(
+                    400,
+                    json!({ "error_code": "refresh_token_not_found", "msg": "Invalid Refresh Token" }),
+                )
+            } else {
+                Reply::json(200, json!([]))
+            }
+        })
+        .await;
+        let client = server.client();
+        sign_in(&client, &token(1, 10));
+        let mut events = client.on_auth_state_change();
+
+        let res = get(&client).await;
+
+        assert!(matches!(res, Err(Error::Auth { status: 400, .. })));
+        assert_eq!(client.access_token(), None);
+        assert_eq!(server.count("rest/v1"), 0);
+        assert!(matches!(
+            events.try_recv().map(|change| change.event),
+            Ok(AuthEvent::SignedOut)
+        ));
+    }
 }
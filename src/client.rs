@@ -1,16 +1,78 @@
-use reqwest::StatusCode;
-use serde::{Deserialize, Serialize};
+use std::{
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+use reqwest::{StatusCode, header::HeaderMap};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+pub use crate::error::Error;
+use crate::mfa::Factor;
+use crate::retry::RetryPolicy;
+use crate::session::{SessionStorage, SessionStore};
+
+const USER_AGENT: &str = concat!("supa_rs/", env!("CARGO_PKG_VERSION"));
 
 #[derive(Debug, Clone)]
 pub struct SupabaseClient {
     pub base_url: String,
     pub api_key: String,
-    pub access_token: Option<String>,
-    pub refresh_token: Option<String>,
+    // postgres schema for table and function calls, postgrest's default
+    // (usually public) when unset
+    pub db_schema: Option<String>,
+    pub(crate) session: SessionStore,
+    pub(crate) http_options: HttpOptions,
+    pub(crate) retry_policy: RetryPolicy,
+    // built on first use and shared by every clone so they all draw from
+    // the same connection pool
+    http: Arc<OnceLock<reqwest::Client>>,
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct HttpOptions {
+    pub connect_timeout: Option<Duration>,
+    pub read_timeout: Option<Duration>,
+    pub timeout: Option<Duration>,
+    pub proxy: Option<reqwest::Proxy>,
+    pub user_agent: Option<String>,
+    pub default_headers: HeaderMap,
+    pub http1_only: bool,
+    pub http2_prior_knowledge: bool,
+}
+
+impl HttpOptions {
+    fn build(&self) -> Result<reqwest::Client, reqwest::Error> {
+        let mut builder = reqwest::Client::builder()
+            .user_agent(self.user_agent.as_deref().unwrap_or(USER_AGENT))
+            .default_headers(self.default_headers.clone());
+
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.read_timeout {
+            builder = builder.read_timeout(timeout);
+        }
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(proxy) = self.proxy.clone() {
+            builder = builder.proxy(proxy);
+        }
+        if self.http1_only {
+            builder = builder.http1_only();
+        }
+        if self.http2_prior_knowledge {
+            builder = builder.http2_prior_knowledge();
+        }
+
+        builder.build()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Method {
     GET,
+    HEAD,
     POST,
     DELETE,
     PATCH,
@@ -21,24 +83,38 @@ pub enum Method {
 pub struct Response<T> {
     pub code: u16,
     pub data: Option<T>,
+    // total row count, when one was requested from postgrest
+    #[serde(default)]
+    pub count: Option<u64>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Error {
-    pub code: Option<String>,
-    #[serde(rename = "statusCode")]
-    pub status_code: Option<String>,
-    pub error_code: Option<String>,
-    pub error: Option<String>,
-    pub msg: Option<String>,
-    pub message: Option<String>,
-    pub details: Option<String>,
+impl Response<String> {
+    // an empty body decodes to no data rather than an error
+    pub(crate) fn json<T>(self) -> Result<Response<T>, Error>
+    where
+        T: DeserializeOwned,
+    {
+        let data = match self.data.as_deref() {
+            Some(txt) if !txt.is_empty() => {
+                Some(serde_json::from_str(txt).map_err(|e| Error::decode(self.code, e))?)
+            }
+            _ => None,
+        };
+
+        Ok(Response {
+            code: self.code,
+            data,
+            count: self.count,
+        })
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LoginResponse {
     pub access_token: String,
     pub refresh_token: String,
+    #[serde(default)]
+    pub user: Option<UserResponse>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -48,6 +124,8 @@ pub struct UserResponse {
     pub is_anonymous: bool,
     pub created_at: String,
     pub updated_at: String,
+    #[serde(default)]
+    pub factors: Vec<Factor>,
 }
 
 impl SupabaseClient {
@@ -55,8 +133,11 @@ impl SupabaseClient {
         Self {
             base_url: "".to_string(),
             api_key: "".to_string(),
-            access_token: None,
-            refresh_token: None,
+            db_schema: None,
+            session: SessionStore::new(),
+            http_options: HttpOptions::default(),
+            retry_policy: RetryPolicy::default(),
+            http: Arc::new(OnceLock::new()),
         }
     }
 
@@ -66,26 +147,148 @@ impl SupabaseClient {
         Self {
             base_url: std::env::var("SUPABASE_URL").expect("require valid SUPABASE_URL"),
             api_key: std::env::var("SUPABASE_KEY").expect("require vaid SUPABASE_KEY"),
-            access_token: None,
-            refresh_token: None,
+            ..self
         }
     }
 
     pub fn base_url(self, url: &str) -> Self {
         Self {
             base_url: url.to_string(),
-            api_key: self.api_key,
-            access_token: None,
-            refresh_token: None,
+            ..self
         }
     }
 
     pub fn api_key(self, key: &str) -> Self {
         Self {
-            base_url: self.base_url,
             api_key: key.to_string(),
-            access_token: None,
-            refresh_token: None,
+            ..self
+        }
+    }
+
+    pub fn session_storage(self, storage: impl SessionStorage + 'static) -> Self {
+        Self {
+            session: SessionStore::load(Arc::new(storage), &self.session.storage_key),
+            ..self
+        }
+    }
+
+    pub fn storage_key(self, key: &str) -> Self {
+        Self {
+            session: SessionStore::load(self.session.storage.clone(), key),
+            ..self
+        }
+    }
+
+    pub fn schema(self, schema: &str) -> Self {
+        Self {
+            db_schema: Some(schema.to_string()),
+            ..self
+        }
+    }
+
+    pub fn retry_policy(self, retry_policy: RetryPolicy) -> Self {
+        Self {
+            retry_policy,
+            ..self
+        }
+    }
+
+    pub fn connect_timeout(self, timeout: Duration) -> Self {
+        self.http_options(|options| options.connect_timeout = Some(timeout))
+    }
+
+    pub fn read_timeout(self, timeout: Duration) -> Self {
+        self.http_options(|options| options.read_timeout = Some(timeout))
+    }
+
+    pub fn timeout(self, timeout: Duration) -> Self {
+        self.http_options(|options| options.timeout = Some(timeout))
+    }
+
+    pub fn proxy(self, proxy: reqwest::Proxy) -> Self {
+        self.http_options(|options| options.proxy = Some(proxy))
+    }
+
+    pub fn user_agent(self, user_agent: &str) -> Self {
+        self.http_options(|options| options.user_agent = Some(user_agent.to_string()))
+    }
+
+    pub fn default_headers(self, headers: HeaderMap) -> Self {
+        self.http_options(|options| options.default_headers = headers)
+    }
+
+    pub fn http1_only(self) -> Self {
+        self.http_options(|options| options.http1_only = true)
+    }
+
+    pub fn http2_prior_knowledge(self) -> Self {
+        self.http_options(|options| options.http2_prior_knowledge = true)
+    }
+
+    // use a preconfigured reqwest client instead of building one from the
+    // http options
+    pub fn http_client(self, client: reqwest::Client) -> Self {
+        Self {
+            http: Arc::new(OnceLock::from(client)),
+            ..self
+        }
+    }
+
+    fn http_options(mut self, f: impl FnOnce(&mut HttpOptions)) -> Self {
+        f(&mut self.http_options);
+
+        Self {
+            http: Arc::new(OnceLock::new()),
+            ..self
+        }
+    }
+
+    pub(crate) fn http(&self) -> Result<&reqwest::Client, reqwest::Error> {
+        if let Some(client) = self.http.get() {
+            return Ok(client);
+        }
+
+        let client = self.http_options.build()?;
+
+        Ok(self.http.get_or_init(|| client))
+    }
+}
+
+impl Default for SupabaseClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RequestOptions {
+    pub headers: HeaderMap,
+    // allows retrying methods other than GET and HEAD
+    pub idempotent: bool,
+}
+
+pub(crate) enum Body {
+    Empty,
+    Json(serde_json::Value),
+    Bytes(Vec<u8>),
+    Form(reqwest::multipart::Form),
+}
+
+impl Body {
+    fn from_parts(body: serde_json::Value, form: Option<reqwest::multipart::Form>) -> Self {
+        match form {
+            Some(form) => Body::Form(form),
+            None => Body::Json(body),
+        }
+    }
+
+    // multipart bodies are streamed and can only be sent once
+    fn try_clone(&self) -> Option<Body> {
+        match self {
+            Body::Empty => Some(Body::Empty),
+            Body::Json(value) => Some(Body::Json(value.clone())),
+            Body::Bytes(bytes) => Some(Body::Bytes(bytes.clone())),
+            Body::Form(_) => None,
         }
     }
 }
@@ -100,148 +303,232 @@ impl SupabaseClient {
         access_token: Option<&str>,
         form: Option<reqwest::multipart::Form>,
     ) -> Result<Response<String>, Error> {
+        self.request_with(
+            method,
+            path,
+            body,
+            access_token,
+            form,
+            &RequestOptions::default(),
+        )
+        .await
+    }
+
+    pub async fn request_with(
+        &self,
+        method: Method,
+        path: &str,
+        body: serde_json::Value,
+        access_token: Option<&str>,
+        form: Option<reqwest::multipart::Form>,
+        options: &RequestOptions,
+    ) -> Result<Response<String>, Error> {
+        let res = self
+            .send(
+                method,
+                path,
+                Body::from_parts(body, form),
+                access_token,
+                options,
+            )
+            .await?;
+
+        read_text(path, res).await
+    }
+
+    pub async fn request_bytes(
+        &self,
+        path: &str,
+        access_token: Option<&str>,
+    ) -> Result<Response<Vec<u8>>, Error> {
+        let res = self
+            .send(
+                Method::GET,
+                path,
+                Body::Empty,
+                access_token,
+                &RequestOptions::default(),
+            )
+            .await?;
+
+        read_bytes(path, res).await
+    }
+
+    // sends the request, retrying transient failures of idempotent calls
+    // according to the retry policy
+    pub(crate) async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        body: Body,
+        access_token: Option<&str>,
+        options: &RequestOptions,
+    ) -> Result<reqwest::Response, Error> {
+        let policy = &self.retry_policy;
+        let retryable = options.idempotent || matches!(method, Method::GET | Method::HEAD);
+
+        let mut body = Some(body);
+        let mut attempt = 1;
+        loop {
+            let current = body.take().unwrap_or(Body::Empty);
+            body = current.try_clone();
+            let can_retry = retryable && body.is_some() && attempt < policy.max_attempts;
+
+            let res = self
+                .send_once(method, path, current, access_token, options)
+                .await;
+
+            if !can_retry {
+                return Ok(res?);
+            }
+
+            let delay = match &res {
+                Ok(r) if policy.should_retry_status(r.status().as_u16()) => {
+                    policy.delay_for(attempt, r)
+                }
+                Err(e) if policy.should_retry_error(e) => policy.delay(attempt),
+                _ => return Ok(res?),
+            };
+
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    async fn send_once(
+        &self,
+        method: Method,
+        path: &str,
+        body: Body,
+        access_token: Option<&str>,
+        options: &RequestOptions,
+    ) -> Result<reqwest::Response, reqwest::Error> {
         let url = format!("{}/{}", self.base_url, path);
 
-        let client = reqwest::Client::new();
+        let client = self.http()?;
         let builder = match method {
             Method::GET => client.get(&url),
+            Method::HEAD => client.head(&url),
             Method::POST => client.post(&url),
             Method::DELETE => client.delete(&url),
             Method::PATCH => client.patch(&url),
             Method::PUT => client.put(&url),
         };
 
-        let prefer_return = if url.contains("select=") {
-            "return=representation"
-        } else {
-            "return=minimal"
-        };
-
-        let builder = if access_token.unwrap_or("") != "" {
-            builder
-                .header("apikey", self.api_key.clone())
-                .header(
-                    "Authorization",
-                    format!("Bearer {}", access_token.unwrap()),
-                )
-        } else {
-            builder
+        let builder = match access_token.filter(|token| !token.is_empty()) {
+            Some(token) => builder
                 .header("apikey", self.api_key.clone())
+                .header("Authorization", format!("Bearer {}", token)),
+            None => builder.header("apikey", self.api_key.clone()),
         };
 
-        let builder = if let Some(form) = form {
-            builder
-                .multipart(form)
-        } else {
-            builder
+        let builder = match body {
+            Body::Empty => builder,
+            Body::Json(body) => builder
                 .header("Content-Type", "application/json")
-                .header("Prefer", prefer_return)
-                .json(&body)
+                .json(&body),
+            Body::Bytes(bytes) => builder.body(bytes),
+            Body::Form(form) => builder.multipart(form),
         };
 
-        let res = builder.send().await.map_err(|e| Error {
-            code: Some(
-                e.status()
-                    .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
-                    .as_str()
-                    .to_string(),
-            ),
-            msg: Some(e.to_string()),
-            error_code: None,
-            details: None,
-            message: None,
-            error: None,
-            status_code: None,
-        })?;
-
-        let status = res.status();
-        let txt = res.text().await.unwrap_or("".to_string());
-
-        if status == StatusCode::OK
-            || status == StatusCode::CREATED
-            || status == StatusCode::NO_CONTENT
-        {
-            return Ok(Response {
-                code: status.as_u16(),
-                data: Some(txt),
-            });
-        }
-
-        match serde_json::from_str::<Error>(&txt) {
-            Ok(res) => Err(Error {
-                code: Some(status.as_str().to_string()),
-                error_code: res.error_code,
-                msg: res.msg,
-                message: res.message,
-                details: res.details,
-                error: res.error,
-                status_code: None,
-            }),
-            Err(_) => Err(Error {
-                code: Some(status.as_str().to_string()),
-                msg: None,
-                error_code: None,
-                details: None,
-                message: None,
-                error: None,
-                status_code: None,
-            }),
+        builder.headers(options.headers.clone()).send().await
+    }
+
+    // sends with the session token, refreshing it once and retrying if the
+    // server rejects it with 401
+    pub(crate) async fn authed_send(
+        &self,
+        method: Method,
+        path: &str,
+        body: Body,
+        options: &RequestOptions,
+    ) -> Result<reqwest::Response, Error> {
+        let token = self.bearer().await?;
+        let retry_body = body.try_clone();
+
+        let res = self
+            .send(method, path, body, token.as_deref(), options)
+            .await?;
+
+        match retry_body {
+            Some(body) if res.status() == StatusCode::UNAUTHORIZED && token.is_some() => {
+                match self.refresh_stale(token.as_deref()).await? {
+                    Some(fresh) => self.send(method, path, body, Some(&fresh), options).await,
+                    None => Ok(res),
+                }
+            }
+            _ => Ok(res),
         }
     }
 
-    pub async fn request_bytes(
+    pub(crate) async fn authed_request(
+        &self,
+        method: Method,
+        path: &str,
+        body: serde_json::Value,
+        form: Option<reqwest::multipart::Form>,
+    ) -> Result<Response<String>, Error> {
+        self.authed_request_with(method, path, body, form, &RequestOptions::default())
+            .await
+    }
+
+    pub(crate) async fn authed_request_with(
+        &self,
+        method: Method,
+        path: &str,
+        body: serde_json::Value,
+        form: Option<reqwest::multipart::Form>,
+        options: &RequestOptions,
+    ) -> Result<Response<String>, Error> {
+        let res = self
+            .authed_send(method, path, Body::from_parts(body, form), options)
+            .await?;
+
+        read_text(path, res).await
+    }
+
+    pub(crate) async fn authed_request_bytes(
         &self,
         path: &str,
-        access_token: Option<&str>,
     ) -> Result<Response<Vec<u8>>, Error> {
-        let url = format!("{}/{}", self.base_url, path);
+        let res = self
+            .authed_send(Method::GET, path, Body::Empty, &RequestOptions::default())
+            .await?;
 
-        let client = reqwest::Client::new();
-        let res = client
-            .get(&url)
-            .header("apikey", self.api_key.clone())
-            .header(
-                "Authorization",
-                format!("Bearer {}", access_token.unwrap_or("")),
-            )
-            .send()
-            .await
-            .map_err(|e| Error {
-                code: Some(
-                    e.status()
-                        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
-                        .as_str()
-                        .to_string(),
-                ),
-                msg: Some(e.to_string()),
-                error_code: None,
-                details: None,
-                message: None,
-                error: None,
-                status_code: None,
-            })?;
-
-        let status = res.status();
-        let bytes = res.bytes().await.unwrap_or(vec![].into());
-
-        if status == StatusCode::OK
-            || status == StatusCode::CREATED
-            || status == StatusCode::NO_CONTENT
-        {
-            return Ok(Response {
-                code: status.as_u16(),
-                data: Some(bytes.to_vec()),
-            });
-        }
-
-        Err(Error {
-            code: Some(status.as_str().to_string()),
-            error_code: None,
-            error: None,
-            msg: None,
-            message: None,
-            details: None,
-            status_code: None,
-        })
+        read_bytes(path, res).await
     }
 }
+
+async fn read_text(path: &str, res: reqwest::Response) -> Result<Response<String>, Error> {
+    let status = res.status();
+    let txt = res.text().await?;
+
+    if status.is_success() {
+        return Ok(Response {
+            code: status.as_u16(),
+            data: Some(txt),
+            count: None,
+        });
+    }
+
+    Err(Error::from_response(path, status.as_u16(), &txt))
+}
+
+async fn read_bytes(path: &str, res: reqwest::Response) -> Result<Response<Vec<u8>>, Error> {
+    let status = res.status();
+    let bytes = res.bytes().await?;
+
+    if status.is_success() {
+        return Ok(Response {
+            code: status.as_u16(),
+            data: Some(bytes.to_vec()),
+            count: None,
+        });
+    }
+
+    Err(Error::from_response(
+        path,
+        status.as_u16(),
+        &String::from_utf8_lossy(&bytes),
+    ))
+}
@@ -0,0 +1,409 @@
+use reqwest::header::{ACCEPT, HeaderValue};
+use serde_json::{Map, Value};
+
+use crate::client::{Body, Error, Method, RequestOptions, Response, SupabaseClient};
+
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "type",
+    "unsafe", "use", "where", "while", "abstract", "become", "box", "do", "final", "gen", "macro",
+    "override", "priv", "try", "typeof", "unsized", "virtual", "yield",
+];
+
+#[derive(Debug, Clone, Default)]
+pub struct GenOptions {
+    // adds `#[derive(SupabaseTable)]` to tables and views, which needs the
+    // `derive` feature in the generated code's crate
+    pub derive: bool,
+}
+
+impl SupabaseClient {
+    // the OpenAPI (swagger 2.0) document postgrest publishes for the schema
+    pub async fn openapi(&self) -> Result<Response<Value>, Error> {
+        let mut options = RequestOptions::default();
+        options
+            .headers
+            .insert(ACCEPT, HeaderValue::from_static("application/openapi+json"));
+        if let Some(schema) = &self.db_schema {
+            let schema = HeaderValue::from_str(schema).map_err(|_| Error::InvalidArgument {
+                message: format!("invalid schema: {}", schema),
+            })?;
+            options.headers.insert("Accept-Profile", schema);
+        }
+
+        let path = "rest/v1/";
+        let res = self
+            .authed_send(Method::GET, path, Body::Empty, &options)
+            .await?;
+
+        let code = res.status().as_u16();
+        let txt = res.text().await?;
+        if !(200..300).contains(&code) {
+            return Err(Error::from_response(path, code, &txt));
+        }
+
+        Response {
+            code,
+            data: Some(txt),
+            count: None,
+        }
+        .json()
+    }
+}
+
+// rust source with a struct per table and view, and an argument struct per
+// function, from a postgrest OpenAPI document
+pub fn generate_types(spec: &Value, options: &GenOptions) -> Result<String, Error> {
+    let definitions = spec
+        .get("definitions")
+        .and_then(Value::as_object)
+        .ok_or_else(|| Error::InvalidArgument {
+            message: "OpenAPI document has no definitions".to_string(),
+        })?;
+    let paths = spec.get("paths").and_then(Value::as_object);
+
+    let mut out = String::from("// generated by `supa_rs gen types`, do not edit\n\n");
+    out += "use serde::{Deserialize, Serialize};\n";
+
+    for (name, definition) in definitions {
+        // views only expose reads, so they have no post operation
+        let writable = paths
+            .and_then(|p| p.get(&format!("/{}", name)))
+            .is_some_and(|p| p.get("post").is_some());
+        let kind = if writable { "table" } else { "view" };
+
+        out += &format!("\n// {} `{}`\n", kind, name);
+        if let Some(description) = definition.get("description").and_then(Value::as_str) {
+            for line in description.lines() {
+                out += &format!("// {}\n", line);
+            }
+        }
+
+        if options.derive {
+            out += "#[derive(Debug, Clone, Serialize, Deserialize, supa_rs::SupabaseTable)]\n";
+            out += &format!("#[supabase(table = {:?})]\n", name);
+        } else {
+            out += "#[derive(Debug, Clone, Serialize, Deserialize)]\n";
+        }
+        out += &structure(name, definition, options.derive, false);
+        out += &format!(
+            "\nimpl {} {{\n    pub const TABLE: &'static str = {:?};\n}}\n",
+            type_name(name),
+            name
+        );
+    }
+
+    for (path, item) in paths.into_iter().flatten() {
+        let Some(function) = path.strip_prefix("/rpc/") else {
+            continue;
+        };
+
+        // the arguments are the schema of the post body parameter
+        let args = item
+            .get("post")
+            .and_then(|p| p.get("parameters"))
+            .and_then(Value::as_array)
+            .and_then(|params| {
+                params
+                    .iter()
+                    .find(|p| p.get("in").and_then(Value::as_str) == Some("body"))
+            })
+            .and_then(|p| p.get("schema"))
+            .cloned()
+            .unwrap_or_else(|| Value::Object(Map::new()));
+
+        let name = format!("{}_args", function);
+        out += &format!("\n// arguments of function `{}`\n", function);
+        out += "#[derive(Debug, Clone, Serialize, Deserialize)]\n";
+        out += &structure(&name, &args, false, true);
+        out += &format!(
+            "\nimpl {} {{\n    pub const FUNCTION: &'static str = {:?};\n}}\n",
+            type_name(&name),
+            function
+        );
+    }
+
+    Ok(out)
+}
+
+fn structure(name: &str, definition: &Value, derive: bool, skip_none: bool) -> String {
+    let required = definition
+        .get("required")
+        .and_then(Value::as_array)
+        .map(|r| r.iter().filter_map(Value::as_str).collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    let mut out = format!("pub struct {} {{\n", type_name(name));
+    let properties = definition.get("properties").and_then(Value::as_object);
+    for (column, property) in properties.into_iter().flatten() {
+        if let Some(description) = property.get("description").and_then(Value::as_str) {
+            // postgrest appends key notes such as `<pk/>` to the comment
+            for line in description.lines().filter(|l| !l.trim().is_empty()) {
+                out += &format!("    // {}\n", line.trim());
+            }
+        }
+
+        let field = field_name(column);
+        if field.trim_start_matches("r#") != column {
+            out += &format!("    #[serde(rename = {:?})]\n", column);
+            if derive {
+                out += &format!("    #[supabase(column = {:?})]\n", column);
+            }
+        }
+        // identity, serial and defaulted columns can be left out of inserts
+        if derive && property.get("default").is_some() {
+            out += "    #[supabase(default)]\n";
+        }
+
+        let ty = rust_type(property);
+        let ty = if required.contains(&column.as_str()) {
+            ty
+        } else {
+            // an omitted argument takes the function's default, null would not
+            if skip_none {
+                out += "    #[serde(skip_serializing_if = \"Option::is_none\")]\n";
+            }
+            format!("Option<{}>", ty)
+        };
+        out += &format!("    pub {}: {},\n", field, ty);
+    }
+    out += "}\n";
+    out
+}
+
+fn rust_type(property: &Value) -> String {
+    let format = property.get("format").and_then(Value::as_str).unwrap_or("");
+
+    if let Some(element) = format.strip_suffix("[]") {
+        return format!(
+            "Vec<{}>",
+            format_type(element).unwrap_or("serde_json::Value")
+        );
+    }
+    if let Some(ty) = format_type(format) {
+        return ty.to_string();
+    }
+
+    match property.get("type").and_then(Value::as_str) {
+        Some("integer") => "i64".to_string(),
+        Some("number") => "f64".to_string(),
+        Some("boolean") => "bool".to_string(),
+        Some("string") => "String".to_string(),
+        Some("array") => match property.get("items") {
+            Some(items) => format!("Vec<{}>", rust_type(items)),
+            None => "Vec<serde_json::Value>".to_string(),
+        },
+        _ => "serde_json::Value".to_string(),
+    }
+}
+
+// postgres type names, as postgrest puts them in `format`
+fn format_type(format: &str) -> Option<&'static str> {
+    let ty = match format {
+        "smallint" | "int2" => "i16",
+        "integer" | "int" | "int4" => "i32",
+        "bigint" | "int8" => "i64",
+        "real" | "float4" => "f32",
+        "double precision" | "float8" | "numeric" | "decimal" => "f64",
+        "boolean" | "bool" => "bool",
+        "json" | "jsonb" => "serde_json::Value",
+        "text"
+        | "character varying"
+        | "varchar"
+        | "character"
+        | "char"
+        | "citext"
+        | "uuid"
+        | "date"
+        | "time without time zone"
+        | "time with time zone"
+        | "timestamp without time zone"
+        | "timestamp with time zone"
+        | "interval"
+        | "inet"
+        | "cidr"
+        | "macaddr"
+        | "bytea" => "String",
+        _ => return None,
+    };
+    Some(ty)
+}
+
+// `user_profiles` -> `UserProfiles`
+fn type_name(name: &str) -> String {
+    let mut out = String::new();
+    let mut upper = true;
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            if upper {
+                out.extend(c.to_uppercase());
+            } else {
+                out.push(c);
+            }
+            upper = false;
+        } else {
+            upper = true;
+        }
+    }
+    if out.is_empty() || out.starts_with(|c: char| c.is_ascii_digit()) {
+        out.insert(0, 'T');
+    }
+    out
+}
+
+fn field_name(column: &str) -> String {
+    let mut out = column
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    if out.is_empty() || out.starts_with(|c: char| c.is_ascii_digit()) {
+        out.insert(0, '_');
+    }
+    if KEYWORDS.contains(&out.as_str()) {
+        out = match out.as_str() {
+            // not allowed as raw identifiers
+            "self" | "Self" | "super" | "crate" => format!("{}_", out),
+            _ => format!("r#{}", out),
+        };
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn fixture() -> Value {
+        json!({
+            "definitions": {
+                "todos": {
+                    "required": ["id", "title"],
+                    "properties": {
+                        "id": {
+                            "description": "Note:\nThis is a Primary Key.<pk/>",
+                            "format": "bigint",
+                            "type": "integer",
+                            "default": "nextval('todos_id_seq'::regclass)",
+                        },
+                        "title": { "format": "text", "type": "string" },
+                        "type": { "format": "text", "type": "string" },
+                        "self": { "format": "boolean", "type": "boolean" },
+                        "created-at": {
+                            "format": "timestamp with time zone",
+                            "type": "string",
+                            "default": "now()",
+                        },
+                        "tags": { "format": "text[]", "type": "array" },
+                    },
+                },
+                "todo_stats": {
+                    "description": "open todos per day",
+                    "properties": {
+                        "done": { "format": "bigint", "type": "integer" },
+                    },
+                },
+            },
+            "paths": {
+                "/todos": { "get": {}, "post": {} },
+                "/todo_stats": { "get": {} },
+                "/rpc/add_todo": {
+                    "post": {
+                        "parameters": [
+                            { "in": "header", "name": "Prefer" },
+                            {
+                                "in": "body",
+                                "name": "args",
+                                "schema": {
+                                    "required": ["title"],
+                                    "properties": {
+                                        "title": { "format": "text", "type": "string" },
+                                        "priority": { "format": "integer", "type": "integer" },
+                                    },
+                                },
+                            },
+                        ],
+                    },
+                },
+            },
+        })
+    }
+
+    const EXPECTED: &str = r#"// generated by `supa_rs gen types`, do not edit
+
+use serde::{Deserialize, Serialize};
+
+// view `todo_stats`
+// open todos per day
+#[derive(Debug, Clone, Serialize, Deserialize, supa_rs::SupabaseTable)]
+#[supabase(table = "todo_stats")]
+pub struct TodoStats {
+    pub done: Option<i64>,
+}
+
+impl TodoStats {
+    pub const TABLE: &'static str = "todo_stats";
+}
+
+// table `todos`
+#[derive(Debug, Clone, Serialize, Deserialize, supa_rs::SupabaseTable)]
+#[supabase(table = "todos")]
+pub struct Todos {
+    #[serde(rename = "created-at")]
+    #[supabase(column = "created-at")]
+    #[supabase(default)]
+    pub created_at: Option<String>,
+    // Note:
+    // This is a Primary Key.<pk/>
+    #[supabase(default)]
+    pub id: i64,
+    #[serde(rename = "self")]
+    #[supabase(column = "self")]
+    pub self_: Option<bool>,
+    pub tags: Option<Vec<String>>,
+    pub title: String,
+    pub r#type: Option<String>,
+}
+
+impl Todos {
+    pub const TABLE: &'static str = "todos";
+}
+
+// arguments of function `add_todo`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddTodoArgs {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>,
+    pub title: String,
+}
+
+impl AddTodoArgs {
+    pub const FUNCTION: &'static str = "add_todo";
+}
+"#;
+
+    #[test]
+    fn generates_fixture() {
+        let out = generate_types(&fixture(), &GenOptions { derive: true }).unwrap();
+        assert_eq!(out, EXPECTED);
+    }
+
+    #[test]
+    fn leaves_out_derive_attributes_by_default() {
+        let out = generate_types(&fixture(), &GenOptions::default()).unwrap();
+
+        assert!(!out.contains("supabase"));
+        assert!(out.contains(
+            "    #[serde(rename = \"created-at\")]\n    pub created_at: Option<String>,\n"
+        ));
+        assert!(out.contains("    // This is a Primary Key.<pk/>\n    pub id: i64,\n"));
+    }
+
+    #[test]
+    fn rejects_a_document_without_definitions() {
+        let res = generate_types(&json!({ "paths": {} }), &GenOptions::default());
+        assert!(matches!(res, Err(Error::InvalidArgument { .. })));
+    }
+}
@@ -0,0 +1,251 @@
+use std::fmt;
+
+use serde::Deserialize;
+
+#[derive(Debug)]
+pub enum Error {
+    Network {
+        source: reqwest::Error,
+    },
+    Timeout {
+        source: reqwest::Error,
+    },
+    Decode {
+        status: u16,
+        source: serde_json::Error,
+    },
+    Postgrest {
+        status: u16,
+        code: Option<String>,
+        message: String,
+        details: Option<String>,
+        hint: Option<String>,
+    },
+    // single() matched no rows
+    NoRows {
+        status: u16,
+    },
+    // single() or maybe_single() matched more than one row
+    MultipleRows {
+        status: u16,
+        rows: Option<u64>,
+    },
+    Auth {
+        status: u16,
+        error_code: Option<String>,
+        msg: String,
+    },
+    Storage {
+        status: u16,
+        error: Option<String>,
+        message: String,
+    },
+    // the relay in front of edge functions failed to reach the function
+    FunctionsRelay {
+        status: u16,
+        message: String,
+    },
+    // the function itself answered with an error status
+    Functions {
+        status: u16,
+        body: String,
+    },
+    Realtime {
+        message: String,
+    },
+    // the PKCE code verifier could not be created, stored or found
+    Pkce {
+        message: String,
+    },
+    // rejected before anything was sent
+    InvalidArgument {
+        message: String,
+    },
+    // an error status whose body did not match the service's error format
+    Http {
+        status: u16,
+        body: String,
+    },
+}
+
+impl Error {
+    // transport, realtime and argument errors never got an http response,
+    // so they have no status
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            Error::Network { source } | Error::Timeout { source } => {
+                source.status().map(|s| s.as_u16())
+            }
+            Error::Realtime { .. } | Error::Pkce { .. } | Error::InvalidArgument { .. } => None,
+            Error::Decode { status, .. }
+            | Error::Postgrest { status, .. }
+            | Error::NoRows { status }
+            | Error::MultipleRows { status, .. }
+            | Error::Auth { status, .. }
+            | Error::Storage { status, .. }
+            | Error::FunctionsRelay { status, .. }
+            | Error::Functions { status, .. }
+            | Error::Http { status, .. } => Some(*status),
+        }
+    }
+
+    pub(crate) fn decode(status: u16, source: serde_json::Error) -> Self {
+        Error::Decode { status, source }
+    }
+
+    // picks the error format of the service the path belongs to
+    pub(crate) fn from_response(path: &str, status: u16, body: &str) -> Self {
+        let parsed = if path.starts_with("rest/v1") {
+            serde_json::from_str::<PostgrestBody>(body)
+                .ok()
+                .map(|e| Error::Postgrest {
+                    status,
+                    code: e.code,
+                    message: e.message,
+                    details: e.details,
+                    hint: e.hint,
+                })
+        } else if path.starts_with("auth/v1") {
+            serde_json::from_str::<AuthBody>(body)
+                .ok()
+                .filter(|e| e.msg.is_some() || e.error.is_some() || e.message.is_some())
+                .map(|e| Error::Auth {
+                    status,
+                    error_code: e.error_code.or(e.error),
+                    msg: e
+                        .msg
+                        .or(e.error_description)
+                        .or(e.message)
+                        .unwrap_or_default(),
+                })
+        } else if path.starts_with("storage/v1") {
+            serde_json::from_str::<StorageBody>(body)
+                .ok()
+                .map(|e| Error::Storage {
+                    status,
+                    error: e.error,
+                    message: e.message,
+                })
+        } else if path.starts_with("functions/v1") {
+            Some(Error::Functions {
+                status,
+                body: body.to_string(),
+            })
+        } else {
+            None
+        };
+
+        parsed.unwrap_or_else(|| Error::Http {
+            status,
+            body: body.to_string(),
+        })
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(source: reqwest::Error) -> Self {
+        if source.is_timeout() {
+            Error::Timeout { source }
+        } else {
+            Error::Network { source }
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Network { source } => write!(f, "network error: {}", source),
+            Error::Timeout { source } => write!(f, "request timed out: {}", source),
+            Error::Decode { status, source } => {
+                write!(f, "failed to decode response ({}): {}", status, source)
+            }
+            Error::Postgrest {
+                status,
+                code,
+                message,
+                details,
+                hint,
+            } => {
+                write!(f, "postgrest error ({}", status)?;
+                if let Some(code) = code {
+                    write!(f, ", {}", code)?;
+                }
+                write!(f, "): {}", message)?;
+                if let Some(details) = details {
+                    write!(f, " ({})", details)?;
+                }
+                if let Some(hint) = hint {
+                    write!(f, " hint: {}", hint)?;
+                }
+                Ok(())
+            }
+            Error::NoRows { status } => write!(f, "expected one row, got none ({})", status),
+            Error::MultipleRows { status, rows } => match rows {
+                Some(rows) => write!(f, "expected one row, got {} ({})", rows, status),
+                None => write!(f, "expected one row, got several ({})", status),
+            },
+            Error::Auth {
+                status,
+                error_code,
+                msg,
+            } => match error_code {
+                Some(code) => write!(f, "auth error ({}, {}): {}", status, code, msg),
+                None => write!(f, "auth error ({}): {}", status, msg),
+            },
+            Error::Storage {
+                status,
+                error,
+                message,
+            } => match error {
+                Some(error) => write!(f, "storage error ({}, {}): {}", status, error, message),
+                None => write!(f, "storage error ({}): {}", status, message),
+            },
+            Error::FunctionsRelay { status, message } => {
+                write!(f, "functions relay error ({}): {}", status, message)
+            }
+            Error::Functions { status, body } => {
+                write!(f, "functions error ({}): {}", status, body)
+            }
+            Error::Realtime { message } => write!(f, "realtime error: {}", message),
+            Error::Pkce { message } => write!(f, "pkce error: {}", message),
+            Error::InvalidArgument { message } => write!(f, "invalid argument: {}", message),
+            Error::Http { status, body } => write!(f, "http error ({}): {}", status, body),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Network { source } | Error::Timeout { source } => Some(source),
+            Error::Decode { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct PostgrestBody {
+    code: Option<String>,
+    message: String,
+    details: Option<String>,
+    hint: Option<String>,
+}
+
+// gotrue answers with either `error_code`/`msg` or the older oauth style
+// `error`/`error_description`
+#[derive(Deserialize)]
+struct AuthBody {
+    error_code: Option<String>,
+    msg: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+    message: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StorageBody {
+    error: Option<String>,
+    message: String,
+}
@@ -0,0 +1,606 @@
+use serde_json::Value;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operator {
+    Eq,
+    Neq,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    Like,
+    ILike,
+    Is,
+    IsDistinct,
+    In,
+    Contains,
+    ContainedBy,
+    Overlaps,
+    // strictly left of, strictly right of, does not extend to the right of,
+    // does not extend to the left of and adjacent to, on range columns
+    RangeLt,
+    RangeGt,
+    RangeLte,
+    RangeGte,
+    RangeAdjacent,
+    LikeAll,
+    LikeAny,
+    ILikeAll,
+    ILikeAny,
+    // posix regular expressions
+    Match,
+    IMatch,
+    // full text search, optionally with a text search config like "english"
+    Fts(Option<String>),
+    Plfts(Option<String>),
+    Phfts(Option<String>),
+    Wfts(Option<String>),
+}
+
+impl Operator {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Operator::Eq => "eq",
+            Operator::Neq => "neq",
+            Operator::Gt => "gt",
+            Operator::Gte => "gte",
+            Operator::Lt => "lt",
+            Operator::Lte => "lte",
+            Operator::Like => "like",
+            Operator::ILike => "ilike",
+            Operator::Is => "is",
+            Operator::IsDistinct => "isdistinct",
+            Operator::In => "in",
+            Operator::Contains => "cs",
+            Operator::ContainedBy => "cd",
+            Operator::Overlaps => "ov",
+            Operator::RangeLt => "sl",
+            Operator::RangeGt => "sr",
+            Operator::RangeLte => "nxr",
+            Operator::RangeGte => "nxl",
+            Operator::RangeAdjacent => "adj",
+            Operator::LikeAll => "like(all)",
+            Operator::LikeAny => "like(any)",
+            Operator::ILikeAll => "ilike(all)",
+            Operator::ILikeAny => "ilike(any)",
+            Operator::Match => "match",
+            Operator::IMatch => "imatch",
+            Operator::Fts(_) => "fts",
+            Operator::Plfts(_) => "plfts",
+            Operator::Phfts(_) => "phfts",
+            Operator::Wfts(_) => "wfts",
+        }
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Operator::Fts(Some(config))
+            | Operator::Plfts(Some(config))
+            | Operator::Phfts(Some(config))
+            | Operator::Wfts(Some(config)) => format!("{}({})", self.as_str(), config),
+            _ => self.as_str().to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Scalar(Value),
+    // `(a,b)`, as taken by `in`
+    List(Vec<Value>),
+    // a postgres array literal `{a,b}`
+    Array(Vec<Value>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    Condition {
+        column: String,
+        operator: Operator,
+        value: Operand,
+    },
+    Not(Box<Filter>),
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+}
+
+impl Filter {
+    pub fn condition(column: &str, operator: Operator, value: Operand) -> Filter {
+        Filter::Condition {
+            column: column.to_string(),
+            operator,
+            value,
+        }
+    }
+
+    pub fn eq(column: &str, value: impl Into<Value>) -> Filter {
+        Filter::scalar(column, Operator::Eq, value)
+    }
+
+    pub fn neq(column: &str, value: impl Into<Value>) -> Filter {
+        Filter::scalar(column, Operator::Neq, value)
+    }
+
+    pub fn gt(column: &str, value: impl Into<Value>) -> Filter {
+        Filter::scalar(column, Operator::Gt, value)
+    }
+
+    pub fn gte(column: &str, value: impl Into<Value>) -> Filter {
+        Filter::scalar(column, Operator::Gte, value)
+    }
+
+    pub fn lt(column: &str, value: impl Into<Value>) -> Filter {
+        Filter::scalar(column, Operator::Lt, value)
+    }
+
+    pub fn lte(column: &str, value: impl Into<Value>) -> Filter {
+        Filter::scalar(column, Operator::Lte, value)
+    }
+
+    // `%` is accepted as a wildcard and sent as postgrest's `*`
+    pub fn like(column: &str, pattern: &str) -> Filter {
+        Filter::scalar(column, Operator::Like, pattern.replace('%', "*"))
+    }
+
+    pub fn ilike(column: &str, pattern: &str) -> Filter {
+        Filter::scalar(column, Operator::ILike, pattern.replace('%', "*"))
+    }
+
+    // null, true, false or "unknown"
+    pub fn is(column: &str, value: impl Into<Value>) -> Filter {
+        Filter::scalar(column, Operator::Is, value)
+    }
+
+    pub fn in_a<I, V>(column: &str, values: I) -> Filter
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<Value>,
+    {
+        Filter::condition(
+            column,
+            Operator::In,
+            Operand::List(values.into_iter().map(Into::into).collect()),
+        )
+    }
+
+    pub fn contains<I, V>(column: &str, values: I) -> Filter
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<Value>,
+    {
+        Filter::condition(
+            column,
+            Operator::Contains,
+            Operand::Array(values.into_iter().map(Into::into).collect()),
+        )
+    }
+
+    pub fn contained_by<I, V>(column: &str, values: I) -> Filter
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<Value>,
+    {
+        Filter::condition(
+            column,
+            Operator::ContainedBy,
+            Operand::Array(values.into_iter().map(Into::into).collect()),
+        )
+    }
+
+    // a value distinct from the column's, treating null as a comparable value
+    pub fn is_distinct(column: &str, value: impl Into<Value>) -> Filter {
+        Filter::scalar(column, Operator::IsDistinct, value)
+    }
+
+    pub fn overlaps<I, V>(column: &str, values: I) -> Filter
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<Value>,
+    {
+        Filter::condition(
+            column,
+            Operator::Overlaps,
+            Operand::Array(values.into_iter().map(Into::into).collect()),
+        )
+    }
+
+    // the range filters take a range literal such as "[2024-01-01,2024-02-01)"
+    pub fn range_lt(column: &str, range: &str) -> Filter {
+        Filter::scalar(column, Operator::RangeLt, range)
+    }
+
+    pub fn range_gt(column: &str, range: &str) -> Filter {
+        Filter::scalar(column, Operator::RangeGt, range)
+    }
+
+    pub fn range_lte(column: &str, range: &str) -> Filter {
+        Filter::scalar(column, Operator::RangeLte, range)
+    }
+
+    pub fn range_gte(column: &str, range: &str) -> Filter {
+        Filter::scalar(column, Operator::RangeGte, range)
+    }
+
+    pub fn range_adjacent(column: &str, range: &str) -> Filter {
+        Filter::scalar(column, Operator::RangeAdjacent, range)
+    }
+
+    pub fn range_overlaps(column: &str, range: &str) -> Filter {
+        Filter::scalar(column, Operator::Overlaps, range)
+    }
+
+    pub fn like_all<I, S>(column: &str, patterns: I) -> Filter
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Filter::patterns(column, Operator::LikeAll, patterns)
+    }
+
+    pub fn like_any<I, S>(column: &str, patterns: I) -> Filter
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Filter::patterns(column, Operator::LikeAny, patterns)
+    }
+
+    pub fn ilike_all<I, S>(column: &str, patterns: I) -> Filter
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Filter::patterns(column, Operator::ILikeAll, patterns)
+    }
+
+    pub fn ilike_any<I, S>(column: &str, patterns: I) -> Filter
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Filter::patterns(column, Operator::ILikeAny, patterns)
+    }
+
+    pub fn regex_match(column: &str, pattern: &str) -> Filter {
+        Filter::scalar(column, Operator::Match, pattern)
+    }
+
+    pub fn regex_imatch(column: &str, pattern: &str) -> Filter {
+        Filter::scalar(column, Operator::IMatch, pattern)
+    }
+
+    // to_tsquery
+    pub fn fts(column: &str, query: &str, config: Option<&str>) -> Filter {
+        Filter::scalar(column, Operator::Fts(config.map(String::from)), query)
+    }
+
+    // plainto_tsquery
+    pub fn plfts(column: &str, query: &str, config: Option<&str>) -> Filter {
+        Filter::scalar(column, Operator::Plfts(config.map(String::from)), query)
+    }
+
+    // phraseto_tsquery
+    pub fn phfts(column: &str, query: &str, config: Option<&str>) -> Filter {
+        Filter::scalar(column, Operator::Phfts(config.map(String::from)), query)
+    }
+
+    // websearch_to_tsquery
+    pub fn wfts(column: &str, query: &str, config: Option<&str>) -> Filter {
+        Filter::scalar(column, Operator::Wfts(config.map(String::from)), query)
+    }
+
+    // every column equal to its value
+    pub fn matches<I, K, V>(values: I) -> Filter
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: Into<Value>,
+    {
+        Filter::and(
+            values
+                .into_iter()
+                .map(|(column, value)| Filter::eq(column.as_ref(), value)),
+        )
+    }
+
+    pub fn and(filters: impl IntoIterator<Item = Filter>) -> Filter {
+        Filter::And(filters.into_iter().collect())
+    }
+
+    pub fn or(filters: impl IntoIterator<Item = Filter>) -> Filter {
+        Filter::Or(filters.into_iter().collect())
+    }
+
+    fn patterns<I, S>(column: &str, operator: Operator, patterns: I) -> Filter
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Filter::condition(
+            column,
+            operator,
+            Operand::Array(
+                patterns
+                    .into_iter()
+                    .map(|p| Value::String(p.as_ref().replace('%', "*")))
+                    .collect(),
+            ),
+        )
+    }
+
+    fn scalar(column: &str, operator: Operator, value: impl Into<Value>) -> Filter {
+        Filter::condition(column, operator, Operand::Scalar(value.into()))
+    }
+
+    // the unencoded query pair of a filter applied to the whole request
+    pub(crate) fn to_param(&self) -> (String, String) {
+        match self {
+            Filter::Condition {
+                column,
+                operator,
+                value,
+            } => (
+                column.clone(),
+                format!("{}.{}", operator.name(), value.render(false)),
+            ),
+            Filter::Not(inner) => match inner.as_ref() {
+                Filter::Not(filter) => filter.to_param(),
+                filter => {
+                    let (key, value) = filter.to_param();
+                    match filter {
+                        Filter::Condition { .. } => (key, format!("not.{}", value)),
+                        _ => (format!("not.{}", key), value),
+                    }
+                }
+            },
+            Filter::And(filters) => ("and".to_string(), group(filters)),
+            Filter::Or(filters) => ("or".to_string(), group(filters)),
+        }
+    }
+
+    // the form used inside `and`/`or` groups, where reserved characters in
+    // values have to be quoted
+    fn to_nested(&self) -> String {
+        match self {
+            Filter::Condition {
+                column,
+                operator,
+                value,
+            } => format!("{}.{}.{}", column, operator.name(), value.render(true)),
+            Filter::Not(inner) => match inner.as_ref() {
+                Filter::Condition {
+                    column,
+                    operator,
+                    value,
+                } => format!("{}.not.{}.{}", column, operator.name(), value.render(true)),
+                Filter::Not(filter) => filter.to_nested(),
+                filter => format!("not.{}", filter.to_nested()),
+            },
+            Filter::And(filters) => format!("and{}", group(filters)),
+            Filter::Or(filters) => format!("or{}", group(filters)),
+        }
+    }
+}
+
+// `!filter` negates it, `!!filter` is the filter itself
+impl std::ops::Not for Filter {
+    type Output = Filter;
+
+    fn not(self) -> Filter {
+        match self {
+            Filter::Not(filter) => *filter,
+            filter => Filter::Not(Box::new(filter)),
+        }
+    }
+}
+
+impl Operand {
+    fn render(&self, nested: bool) -> String {
+        match self {
+            Operand::Scalar(value) if nested => quote(&scalar(value), ",.:()"),
+            Operand::Scalar(value) => scalar(value),
+            Operand::List(values) => format!(
+                "({})",
+                values
+                    .iter()
+                    .map(|v| quote(&scalar(v), ",.:()"))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+            Operand::Array(values) => format!(
+                "{{{}}}",
+                values
+                    .iter()
+                    .map(|v| match v {
+                        Value::Null => "NULL".to_string(),
+                        Value::String(s) if s.eq_ignore_ascii_case("null") => format!("\"{}\"", s),
+                        v => quote(&scalar(v), ",{}"),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+        }
+    }
+}
+
+fn group(filters: &[Filter]) -> String {
+    format!(
+        "({})",
+        filters
+            .iter()
+            .map(Filter::to_nested)
+            .collect::<Vec<_>>()
+            .join(",")
+    )
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+// wraps the value in double quotes when it is empty or contains a reserved
+// character, quotes, backslashes or whitespace
+fn quote(value: &str, reserved: &str) -> String {
+    let needs_quotes = value.is_empty()
+        || value
+            .chars()
+            .any(|c| reserved.contains(c) || c == '"' || c == '\\' || c.is_whitespace());
+
+    if !needs_quotes {
+        return value.to_string();
+    }
+
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::utils::encode;
+
+    fn param(filter: Filter) -> (String, String) {
+        filter.to_param()
+    }
+
+    #[test]
+    fn top_level_values_are_not_quoted() {
+        let cases = [
+            (Filter::eq("name", "a,b.c:d(e)"), "eq.a,b.c:d(e)"),
+            (Filter::eq("name", "say \"hi\""), "eq.say \"hi\""),
+            (Filter::eq("age", 3), "eq.3"),
+            (Filter::is("deleted_at", Value::Null), "is.null"),
+            (Filter::like("name", "%ab%"), "like.*ab*"),
+            (
+                Filter::fts("body", "cat & dog", Some("english")),
+                "fts(english).cat & dog",
+            ),
+        ];
+
+        for (filter, expected) in cases {
+            let (_, value) = param(filter.clone());
+            assert_eq!(value, expected, "{:?}", filter);
+        }
+    }
+
+    #[test]
+    fn nested_values_quote_reserved_characters() {
+        let cases = [
+            ("plain", "name.eq.plain"),
+            ("a,b", "name.eq.\"a,b\""),
+            ("a.b", "name.eq.\"a.b\""),
+            ("a:b", "name.eq.\"a:b\""),
+            ("f(x)", "name.eq.\"f(x)\""),
+            ("say \"hi\"", "name.eq.\"say \\\"hi\\\"\""),
+            ("back\\slash", "name.eq.\"back\\\\slash\""),
+            ("two words", "name.eq.\"two words\""),
+            ("tab\there", "name.eq.\"tab\there\""),
+            ("", "name.eq.\"\""),
+        ];
+
+        for (value, expected) in cases {
+            let (key, group) = param(Filter::or([Filter::eq("name", value)]));
+            assert_eq!(key, "or");
+            assert_eq!(group, format!("({})", expected), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn negation_and_groups() {
+        let cases = [
+            (!Filter::eq("a", 1), ("a", "not.eq.1")),
+            (!!Filter::eq("a", 1), ("a", "eq.1")),
+            (
+                Filter::or([Filter::eq("a", 1), !Filter::gt("b", 2)]),
+                ("or", "(a.eq.1,b.not.gt.2)"),
+            ),
+            (
+                !Filter::or([Filter::eq("a", 1), Filter::eq("b", 2)]),
+                ("not.or", "(a.eq.1,b.eq.2)"),
+            ),
+            (
+                Filter::and([
+                    Filter::eq("a", 1),
+                    Filter::or([Filter::lt("b", 2), !Filter::and([Filter::is("c", true)])]),
+                ]),
+                ("and", "(a.eq.1,or(b.lt.2,not.and(c.is.true)))"),
+            ),
+            (
+                Filter::matches([("a", json!(1)), ("b", json!("x y"))]),
+                ("and", "(a.eq.1,b.eq.\"x y\")"),
+            ),
+        ];
+
+        for (filter, (key, value)) in cases {
+            assert_eq!(
+                param(filter.clone()),
+                (key.to_string(), value.to_string()),
+                "{:?}",
+                filter
+            );
+        }
+    }
+
+    #[test]
+    fn in_lists_and_array_literals() {
+        let cases = [
+            (Filter::in_a("id", [1, 2, 3]), "in.(1,2,3)"),
+            (Filter::in_a("name", ["a,b", "c"]), "in.(\"a,b\",c)"),
+            (
+                Filter::in_a("name", ["f(x)", "a.b"]),
+                "in.(\"f(x)\",\"a.b\")",
+            ),
+            (Filter::contains("tags", ["a", "b"]), "cs.{a,b}"),
+            (
+                Filter::contains("tags", ["a,b", "{c}"]),
+                "cs.{\"a,b\",\"{c}\"}",
+            ),
+            (Filter::contained_by("tags", ["a.b", "c:d"]), "cd.{a.b,c:d}"),
+            (
+                Filter::overlaps("tags", ["two words"]),
+                "ov.{\"two words\"}",
+            ),
+            (Filter::like_any("name", ["%a", "b%"]), "like(any).{*a,b*}"),
+        ];
+
+        for (filter, expected) in cases {
+            let (_, value) = param(filter.clone());
+            assert_eq!(value, expected, "{:?}", filter);
+        }
+    }
+
+    #[test]
+    fn null_in_arrays() {
+        let cases = [
+            (vec![Value::Null, json!("a")], "cs.{NULL,a}"),
+            (vec![json!("null")], "cs.{\"null\"}"),
+            (vec![json!("NULL")], "cs.{\"NULL\"}"),
+            (vec![json!(1), Value::Null], "cs.{1,NULL}"),
+        ];
+
+        for (values, expected) in cases {
+            let (_, value) = param(Filter::contains("tags", values.clone()));
+            assert_eq!(value, expected, "{:?}", values);
+        }
+    }
+
+    #[test]
+    fn encodes_every_reserved_byte() {
+        let cases = [
+            ("eq.a b", "eq.a%20b"),
+            ("eq.a&b=c", "eq.a%26b%3Dc"),
+            ("in.(\"a,b\")", "in.%28%22a%2Cb%22%29"),
+            ("eq.50%", "eq.50%25"),
+            ("eq.é", "eq.%C3%A9"),
+            ("like.a*_~-", "like.a%2A_~-"),
+        ];
+
+        for (value, expected) in cases {
+            assert_eq!(encode(value), expected, "{:?}", value);
+        }
+    }
+}
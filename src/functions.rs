@@ -0,0 +1,293 @@
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE, HeaderMap, HeaderName, HeaderValue};
+use serde::de::DeserializeOwned;
+
+use crate::{
+    client::{Body, Error, Method, RequestOptions, Response, SupabaseClient},
+    utils::encode,
+};
+
+#[derive(Debug, Clone)]
+pub struct FunctionsClient {
+    pub client: SupabaseClient,
+    pub region: Option<String>,
+}
+
+pub struct FunctionInvoke {
+    client: SupabaseClient,
+    name: String,
+    method: Method,
+    headers: HeaderMap,
+    body: Body,
+    region: Option<String>,
+    invalid: Option<String>,
+}
+
+impl SupabaseClient {
+    pub fn functions(&self) -> FunctionsClient {
+        FunctionsClient {
+            client: self.clone(),
+            region: None,
+        }
+    }
+}
+
+impl FunctionsClient {
+    // default region for every invocation, "any" lets the platform choose
+    pub fn region(self, region: &str) -> Self {
+        Self {
+            region: Some(region.to_string()),
+            ..self
+        }
+    }
+
+    pub fn invoke(&self, name: &str) -> FunctionInvoke {
+        FunctionInvoke {
+            client: self.client.clone(),
+            name: name.to_string(),
+            method: Method::POST,
+            headers: HeaderMap::new(),
+            body: Body::Empty,
+            region: self.region.clone(),
+            invalid: None,
+        }
+    }
+}
+
+impl FunctionInvoke {
+    pub fn method(self, method: Method) -> Self {
+        Self { method, ..self }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        match (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            (Ok(name), Ok(value)) => {
+                self.headers.insert(name, value);
+            }
+            _ => self.invalid = Some(format!("invalid header: {}", name)),
+        }
+        self
+    }
+
+    pub fn headers(mut self, headers: HeaderMap) -> Self {
+        self.headers.extend(headers);
+        self
+    }
+
+    pub fn body_json(self, body: serde_json::Value) -> Self {
+        Self {
+            body: Body::Json(body),
+            ..self
+        }
+    }
+
+    pub fn body_bytes(mut self, body: Vec<u8>) -> Self {
+        if !self.headers.contains_key(CONTENT_TYPE) {
+            self.headers.insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static("application/octet-stream"),
+            );
+        }
+        self.body = Body::Bytes(body);
+        self
+    }
+
+    pub fn body_form(self, form: reqwest::multipart::Form) -> Self {
+        Self {
+            body: Body::Form(form),
+            ..self
+        }
+    }
+
+    pub fn region(self, region: &str) -> Self {
+        Self {
+            region: Some(region.to_string()),
+            ..self
+        }
+    }
+
+    pub async fn execute<T>(self) -> Result<Response<T>, Error>
+    where
+        T: DeserializeOwned,
+    {
+        let res = self.text().await?;
+        let code = res.code;
+        let txt = res.data.unwrap_or_default();
+
+        let data = if txt.is_empty() {
+            None
+        } else {
+            Some(serde_json::from_str(&txt).map_err(|e| Error::decode(code, e))?)
+        };
+
+        Ok(Response {
+            code,
+            data,
+            count: None,
+        })
+    }
+
+    pub async fn json(self) -> Result<Response<serde_json::Value>, Error> {
+        self.execute().await
+    }
+
+    pub async fn text(self) -> Result<Response<String>, Error> {
+        let res = self.send().await?;
+        let code = res.status().as_u16();
+
+        Ok(Response {
+            code,
+            data: Some(res.text().await?),
+            count: None,
+        })
+    }
+
+    pub async fn bytes(self) -> Result<Response<Vec<u8>>, Error> {
+        let res = self.send().await?;
+        let code = res.status().as_u16();
+
+        Ok(Response {
+            code,
+            data: Some(res.bytes().await?.to_vec()),
+            count: None,
+        })
+    }
+
+    async fn send(self) -> Result<reqwest::Response, Error> {
+        if let Some(message) = self.invalid {
+            return Err(Error::InvalidArgument { message });
+        }
+
+        let mut path = format!("functions/v1/{}", encode(&self.name));
+        let mut headers = self.headers;
+
+        if let Some(region) = self.region.filter(|r| r != "any") {
+            let value = HeaderValue::from_str(&region).map_err(|_| Error::InvalidArgument {
+                message: format!("invalid region: {}", region),
+            })?;
+            headers.insert("x-region", value);
+            path += &format!("?forceFunctionRegion={}", encode(&region));
+        }
+
+        // functions verify a jwt by default, so without a session the anon
+        // key goes in as the bearer, like realtime does
+        if self.client.access_token().is_none() && !headers.contains_key(AUTHORIZATION) {
+            let value = HeaderValue::from_str(&format!("Bearer {}", self.client.api_key)).map_err(
+                |_| Error::InvalidArgument {
+                    message: "invalid api key".to_string(),
+                },
+            )?;
+            headers.insert(AUTHORIZATION, value);
+        }
+
+        let options = RequestOptions {
+            headers,
+            ..RequestOptions::default()
+        };
+
+        let res = self
+            .client
+            .authed_send(self.method, &path, self.body, &options)
+            .await?;
+
+        let status = res.status().as_u16();
+        let relay_error = res
+            .headers()
+            .get("x-relay-error")
+            .is_some_and(|v| v == "true");
+
+        if relay_error {
+            return Err(Error::FunctionsRelay {
+                status,
+                message: res.text().await?,
+            });
+        }
+
+        if !res.status().is_success() {
+            return Err(Error::from_response(&path, status, &res.text().await?));
+        }
+
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_server::{Reply, Server};
+
+    #[tokio::test]
+    async fn encodes_name_and_region() {
+        let server = Server::start(|_| Reply::json(200, json!({ "ok": true }))).await;
+
+        let res = server
+            .client()
+            .functions()
+            .invoke("hello world")
+            .region("us east")
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(res.data, Some(json!({ "ok": true })));
+
+        let req = &server.requests()[0];
+        assert_eq!(
+            req.path,
+            "functions/v1/hello%20world?forceFunctionRegion=us%20east"
+        );
+        assert_eq!(req.header("x-region"), Some("us east"));
+    }
+
+    #[tokio::test]
+    async fn sends_the_anon_key_without_a_session() {
+        let server = Server::start(|_| Reply::status(200)).await;
+        let functions = server.client().functions();
+
+        functions.invoke("hello").text().await.unwrap();
+        functions
+            .invoke("hello")
+            .header("Authorization", "Bearer custom")
+            .text()
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].header("authorization"), Some("Bearer anon"));
+        assert_eq!(requests[1].header("authorization"), Some("Bearer custom"));
+    }
+
+    #[tokio::test]
+    async fn maps_relay_and_function_errors() {
+        let server = Server::start(|req| {
+            if req.path.starts_with("functions/v1/relay") {
+                Reply::status(502)
+                    .header("x-relay-error", "true")
+                    .body("function not found")
+            } else {
+                Reply::json(400, json!({ "error": "bad input" }))
+            }
+        })
+        .await;
+        let functions = server.client().functions();
+
+        match functions.invoke("relay").text().await {
+            Err(Error::FunctionsRelay { status, message }) => {
+                assert_eq!(status, 502);
+                assert_eq!(message, "function not found");
+            }
+            other => panic!("expected a relay error, got {:?}", other),
+        }
+
+        match functions.invoke("broken").text().await {
+            Err(Error::Functions { status, body }) => {
+                assert_eq!(status, 400);
+                assert_eq!(body, r#"{"error":"bad input"}"#);
+            }
+            other => panic!("expected a function error, got {:?}", other),
+        }
+    }
+}
@@ -1,5 +1,24 @@
 pub mod auth;
 pub mod client;
+pub mod codegen;
+pub mod error;
+pub mod filter;
+pub mod functions;
+pub mod mfa;
 pub mod query;
+pub mod realtime;
+pub mod retry;
+pub mod select;
+pub mod session;
 pub mod storage;
+pub mod table;
+#[cfg(test)]
+mod test_server;
 mod utils;
+
+#[cfg(feature = "derive")]
+pub use supa_rs_derive::SupabaseTable;
+
+// lets derived code name serde without the user depending on it
+#[doc(hidden)]
+pub use serde;
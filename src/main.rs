@@ -1,18 +1,101 @@
-use anyhow::{Ok, Result};
-use supa_rs::client::SupabaseClient;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result, bail};
+use supa_rs::{
+    client::SupabaseClient,
+    codegen::{GenOptions, generate_types},
+};
+
+const USAGE: &str = "usage: supa_rs gen types [--url <supabase url>] [--key <api key>]
+                           [--file <openapi.json>] [--schema <name>]
+                           [--derive] [--output <file>]
+
+reads the OpenAPI document from <url>/rest/v1/, or from --file, and prints
+rust types for its tables, views and functions. --url and --key default to
+SUPABASE_URL and SUPABASE_KEY";
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+
+    match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        ["gen", "types", rest @ ..] => gen_types(rest).await,
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    }
+}
+
+async fn gen_types(args: &[&str]) -> Result<()> {
+    let mut url = None;
+    let mut key = None;
+    let mut file = None;
+    let mut schema = None;
+    let mut output = None;
+    let mut options = GenOptions::default();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .map(|v| v.to_string())
+                .with_context(|| format!("{} needs a value", arg))
+        };
+
+        match *arg {
+            "--url" => url = Some(value()?),
+            "--key" => key = Some(value()?),
+            "--file" => file = Some(PathBuf::from(value()?)),
+            "--schema" => schema = Some(value()?),
+            "--output" => output = Some(PathBuf::from(value()?)),
+            "--derive" => options.derive = true,
+            _ => bail!("unknown argument {}\n\n{}", arg, USAGE),
+        }
+    }
+
+    let spec = match file {
+        Some(file) => {
+            let txt = std::fs::read_to_string(&file)
+                .with_context(|| format!("failed to read {}", file.display()))?;
+            serde_json::from_str(&txt)
+                .with_context(|| format!("{} is not valid json", file.display()))?
+        }
+        None => {
+            dotenv::dotenv().ok();
+
+            let url = url
+                .or_else(|| std::env::var("SUPABASE_URL").ok())
+                .context("pass --url or set SUPABASE_URL")?;
+            let key = key
+                .or_else(|| std::env::var("SUPABASE_KEY").ok())
+                .context("pass --key or set SUPABASE_KEY")?;
 
-    let mut client = SupabaseClient::new().load_env();
+            let mut client = SupabaseClient::new().base_url(&url).api_key(&key);
+            if let Some(schema) = &schema {
+                client = client.schema(schema);
+            }
 
-    let res = client.email_login("test-player@yopmail.com", "12345678").await.unwrap().data.unwrap();
+            client
+                .openapi()
+                .await?
+                .data
+                .context("empty OpenAPI document")?
+        }
+    };
 
-     client.set_session(&res.access_token, &res.refresh_token).await;
+    let types = generate_types(&spec, &options)?;
 
-    let user = client.user().await;
-    
-    println!("{:?}", user);
+    match output {
+        Some(output) => std::fs::write(&output, types)
+            .with_context(|| format!("failed to write {}", output.display()))?,
+        None => print!("{}", types),
+    }
 
     Ok(())
-}
\ No newline at end of file
+}
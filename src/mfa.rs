@@ -0,0 +1,254 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    client::{Error, LoginResponse, Method, Response, SupabaseClient},
+    session::AuthEvent,
+    utils::decode_jwt,
+};
+
+#[derive(Debug, Clone)]
+pub struct MfaClient {
+    pub client: SupabaseClient,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Factor {
+    pub id: String,
+    pub friendly_name: Option<String>,
+    // "totp" or "phone"
+    pub factor_type: String,
+    // "verified" or "unverified"
+    pub status: String,
+    #[serde(default)]
+    pub phone: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "factor_type", rename_all = "lowercase")]
+pub enum EnrollParams {
+    Totp {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        friendly_name: Option<String>,
+        // shown by authenticator apps, defaults to the project's url
+        #[serde(skip_serializing_if = "Option::is_none")]
+        issuer: Option<String>,
+    },
+    Phone {
+        phone: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        friendly_name: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TotpEnrollment {
+    // svg of the qr code to scan with an authenticator app
+    pub qr_code: String,
+    pub secret: String,
+    pub uri: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EnrollResponse {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub factor_type: String,
+    pub friendly_name: Option<String>,
+    #[serde(default)]
+    pub totp: Option<TotpEnrollment>,
+    #[serde(default)]
+    pub phone: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChallengeResponse {
+    pub id: String,
+    #[serde(default, rename = "type")]
+    pub factor_type: Option<String>,
+    pub expires_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnenrollResponse {
+    pub id: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Factors {
+    pub all: Vec<Factor>,
+    // only the verified factors of each type
+    pub totp: Vec<Factor>,
+    pub phone: Vec<Factor>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthenticationMethod {
+    pub method: String,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AuthenticatorAssuranceLevel {
+    // "aal1" or "aal2", none without a session
+    pub current_level: Option<String>,
+    // "aal2" once a verified factor exists, so the user can be asked to step up
+    pub next_level: Option<String>,
+    pub current_authentication_methods: Vec<AuthenticationMethod>,
+}
+
+impl SupabaseClient {
+    pub fn mfa(&self) -> MfaClient {
+        MfaClient {
+            client: self.clone(),
+        }
+    }
+}
+
+impl MfaClient {
+    pub async fn enroll(&self, params: EnrollParams) -> Result<Response<EnrollResponse>, Error> {
+        let body = serde_json::to_value(&params).map_err(|e| Error::InvalidArgument {
+            message: e.to_string(),
+        })?;
+
+        self.client
+            .authed_request(Method::POST, "auth/v1/factors", body, None)
+            .await?
+            .json()
+    }
+
+    // `channel` picks "sms" or "whatsapp" for phone factors
+    pub async fn challenge(
+        &self,
+        factor_id: &str,
+        channel: Option<&str>,
+    ) -> Result<Response<ChallengeResponse>, Error> {
+        let body = match channel {
+            Some(channel) => json!({ "channel": channel }),
+            None => json!({}),
+        };
+
+        self.client
+            .authed_request(
+                Method::POST,
+                &format!("auth/v1/factors/{}/challenge", factor_id),
+                body,
+                None,
+            )
+            .await?
+            .json()
+    }
+
+    // a verified challenge upgrades the session to aal2
+    pub async fn verify(
+        &self,
+        factor_id: &str,
+        challenge_id: &str,
+        code: &str,
+    ) -> Result<Response<LoginResponse>, Error> {
+        let res = self
+            .client
+            .authed_request(
+                Method::POST,
+                &format!("auth/v1/factors/{}/verify", factor_id),
+                json!({
+                    "challenge_id": challenge_id,
+                    "code": code
+                }),
+                None,
+            )
+            .await?
+            .json::<LoginResponse>()?;
+
+        if let Some(login) = &res.data {
+            self.client
+                .store_session(login, AuthEvent::MfaChallengeVerified);
+        }
+
+        Ok(res)
+    }
+
+    pub async fn challenge_and_verify(
+        &self,
+        factor_id: &str,
+        code: &str,
+    ) -> Result<Response<LoginResponse>, Error> {
+        let challenge = self.challenge(factor_id, None).await?;
+        let Some(challenge) = challenge.data else {
+            // an empty challenge id would only fail later at verify
+            return Err(Error::decode(
+                challenge.code,
+                serde::de::Error::missing_field("id"),
+            ));
+        };
+
+        self.verify(factor_id, &challenge.id, code).await
+    }
+
+    pub async fn unenroll(&self, factor_id: &str) -> Result<Response<UnenrollResponse>, Error> {
+        self.client
+            .authed_request(
+                Method::DELETE,
+                &format!("auth/v1/factors/{}", factor_id),
+                json!({}),
+                None,
+            )
+            .await?
+            .json()
+    }
+
+    // the factors of the current user, fetched fresh from the server
+    pub async fn list_factors(&self) -> Result<Response<Factors>, Error> {
+        let res = self.client.user().await?;
+
+        let all = res.data.map(|user| user.factors).unwrap_or_default();
+        let verified = |factor_type: &str| {
+            all.iter()
+                .filter(|f| f.factor_type == factor_type && f.status == "verified")
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+
+        Ok(Response {
+            code: res.code,
+            data: Some(Factors {
+                totp: verified("totp"),
+                phone: verified("phone"),
+                all: all.clone(),
+            }),
+            count: None,
+        })
+    }
+
+    // read from the stored session, without a request
+    pub fn get_authenticator_assurance_level(&self) -> AuthenticatorAssuranceLevel {
+        let session = self.client.session();
+        let Some(claims) = session
+            .access_token
+            .as_deref()
+            .and_then(|token| decode_jwt(token).ok())
+        else {
+            return AuthenticatorAssuranceLevel::default();
+        };
+
+        let has_verified_factor = session
+            .user
+            .iter()
+            .flat_map(|user| &user.factors)
+            .any(|f| f.status == "verified");
+
+        let next_level = if has_verified_factor {
+            Some("aal2".to_string())
+        } else {
+            claims.aal.clone()
+        };
+
+        AuthenticatorAssuranceLevel {
+            current_authentication_methods: claims.authentication_methods(),
+            current_level: claims.aal,
+            next_level,
+        }
+    }
+}
@@ -1,16 +1,180 @@
+use reqwest::header::{ACCEPT, CONTENT_RANGE, HeaderValue, RANGE};
 use serde::de::DeserializeOwned;
-use serde_json::json;
 
-use crate::client::{Error, Method, Response, SupabaseClient};
+use crate::client::{Body, Error, Method, RequestOptions, Response, SupabaseClient};
+use crate::filter::Filter;
+use crate::select::{self, Field};
+use crate::utils::encode;
 
 #[derive(Debug, Clone)]
 pub struct TableSchema {
     pub client: SupabaseClient,
     pub name: String,
+    pub schema: Option<String>,
     pub insert: Option<serde_json::Value>,
     pub update: Option<serde_json::Value>,
     pub delete: bool,
-    pub params: Vec<(String, String, String)>,
+    pub upsert: bool,
+    pub ignore_duplicates: bool,
+    // when false, columns missing from inserted rows get their default value
+    pub default_to_null: bool,
+    pub idempotent: bool,
+    // arguments of a function called with POST
+    pub rpc: Option<serde_json::Value>,
+    pub head: bool,
+    pub count: Option<CountMode>,
+    pub returning: Option<Returning>,
+    // inclusive row range, sent as a `Range` header
+    pub range: Option<(u64, u64)>,
+    // raw query parameters such as select, order and limit
+    pub params: Vec<(String, String)>,
+    pub filters: Vec<Filter>,
+    // a problem found while building the query, reported when it is sent
+    pub invalid: Option<String>,
+}
+
+const OBJECT: &str = "application/vnd.pgrst.object+json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CountMode {
+    Exact,
+    Planned,
+    Estimated,
+}
+
+impl CountMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CountMode::Exact => "exact",
+            CountMode::Planned => "planned",
+            CountMode::Estimated => "estimated",
+        }
+    }
+}
+
+// what a mutation sends back, by default the affected rows when `select` was
+// called and nothing otherwise
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Returning {
+    Minimal,
+    Representation,
+    HeadersOnly,
+}
+
+impl Returning {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Returning::Minimal => "return=minimal",
+            Returning::Representation => "return=representation",
+            Returning::HeadersOnly => "return=headers-only",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RpcOptions {
+    // only fetch the count, without any rows
+    pub head: bool,
+    // call a read-only function with GET, passing the arguments in the url
+    pub get: bool,
+    pub count: Option<CountMode>,
+}
+
+impl RpcOptions {
+    pub fn head(self) -> Self {
+        Self { head: true, ..self }
+    }
+
+    pub fn get(self) -> Self {
+        Self { get: true, ..self }
+    }
+
+    pub fn count(self, count: CountMode) -> Self {
+        Self {
+            count: Some(count),
+            ..self
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExplainFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ExplainOptions {
+    pub analyze: bool,
+    pub verbose: bool,
+    pub settings: bool,
+    pub buffers: bool,
+    pub wal: bool,
+    pub format: ExplainFormat,
+}
+
+impl ExplainOptions {
+    pub fn analyze(self) -> Self {
+        Self {
+            analyze: true,
+            ..self
+        }
+    }
+
+    pub fn verbose(self) -> Self {
+        Self {
+            verbose: true,
+            ..self
+        }
+    }
+
+    pub fn settings(self) -> Self {
+        Self {
+            settings: true,
+            ..self
+        }
+    }
+
+    pub fn buffers(self) -> Self {
+        Self {
+            buffers: true,
+            ..self
+        }
+    }
+
+    pub fn wal(self) -> Self {
+        Self { wal: true, ..self }
+    }
+
+    pub fn format(self, format: ExplainFormat) -> Self {
+        Self { format, ..self }
+    }
+
+    fn media_type(&self) -> String {
+        let options = [
+            (self.analyze, "analyze"),
+            (self.verbose, "verbose"),
+            (self.settings, "settings"),
+            (self.buffers, "buffers"),
+            (self.wal, "wal"),
+        ]
+        .iter()
+        .filter(|(on, _)| *on)
+        .map(|(_, name)| *name)
+        .collect::<Vec<_>>()
+        .join("|");
+
+        let format = match self.format {
+            ExplainFormat::Text => "text",
+            ExplainFormat::Json => "json",
+        };
+
+        format!(
+            "application/vnd.pgrst.plan+{}; for=\"application/json\"; options={};",
+            format, options
+        )
+    }
 }
 
 impl SupabaseClient {
@@ -18,167 +182,420 @@ impl SupabaseClient {
         TableSchema {
             client: self.clone(),
             name: name.to_string(),
+            schema: self.db_schema.clone(),
             params: vec![],
+            filters: vec![],
             insert: None,
             update: None,
             delete: false,
+            upsert: false,
+            ignore_duplicates: false,
+            default_to_null: true,
+            idempotent: false,
+            rpc: None,
+            head: false,
+            count: None,
+            returning: None,
+            range: None,
+            invalid: None,
+        }
+    }
+
+    pub fn rpc(&self, function: &str, args: serde_json::Value) -> TableSchema {
+        self.rpc_with(function, args, RpcOptions::default())
+    }
+
+    pub fn rpc_with(
+        &self,
+        function: &str,
+        args: serde_json::Value,
+        options: RpcOptions,
+    ) -> TableSchema {
+        let mut schema = self.from(&format!("rpc/{}", function));
+        schema.head = options.head;
+        schema.count = options.count;
+
+        if options.head || options.get {
+            // GET and HEAD have no body, so the arguments go in the url
+            let args = match args {
+                serde_json::Value::Object(args) => args,
+                serde_json::Value::Null => serde_json::Map::new(),
+                _ => {
+                    schema.invalid =
+                        Some("rpc arguments must be an object when using GET or HEAD".to_string());
+                    serde_json::Map::new()
+                }
+            };
+            for (name, value) in args {
+                let value = match value {
+                    serde_json::Value::String(s) => s,
+                    serde_json::Value::Array(items) => format!(
+                        "{{{}}}",
+                        items
+                            .iter()
+                            .map(|v| v.to_string())
+                            .collect::<Vec<_>>()
+                            .join(",")
+                    ),
+                    value => value.to_string(),
+                };
+                schema.params.push((name, value));
+            }
+        } else {
+            schema.rpc = Some(args);
         }
+
+        schema
     }
 }
 
 impl TableSchema {
-    pub fn select(&mut self, columns: &str) -> TableSchema {
-        self.params
-            .push(("select".to_string(), columns.to_string(), "".to_string()));
+    // overrides the client's schema for this query
+    pub fn schema(&mut self, schema: &str) -> TableSchema {
+        self.schema = Some(schema.to_string());
         self.clone()
     }
 
-    pub fn eq(&mut self, column: &str, value: serde_json::Value) -> TableSchema {
-        self.params
-            .push(("eq".to_string(), column.to_string(), value.to_string()));
-        self.clone()
+    pub fn select(&mut self, columns: &str) -> TableSchema {
+        self.set_param("select", columns.to_string())
     }
 
-    pub fn neq(&mut self, column: &str, value: serde_json::Value) -> TableSchema {
-        self.params
-            .push(("neq".to_string(), column.to_string(), value.to_string()));
-        self.clone()
+    pub fn select_fields<I>(&mut self, fields: I) -> TableSchema
+    where
+        I: IntoIterator<Item = Field>,
+    {
+        let fields = fields.into_iter().collect::<Vec<_>>();
+        self.set_param("select", select::render(&fields))
     }
 
-    pub fn gt(&mut self, column: &str, value: serde_json::Value) -> TableSchema {
-        self.params
-            .push(("gt".to_string(), column.to_string(), value.to_string()));
+    pub fn filter(&mut self, filter: Filter) -> TableSchema {
+        self.filters.push(filter);
         self.clone()
     }
 
-    pub fn gte(&mut self, column: &str, value: serde_json::Value) -> TableSchema {
-        self.params
-            .push(("gte".to_string(), column.to_string(), value.to_string()));
-        self.clone()
+    pub fn eq(
+        &mut self,
+        column: impl AsRef<str>,
+        value: impl Into<serde_json::Value>,
+    ) -> TableSchema {
+        self.filter(Filter::eq(column.as_ref(), value))
     }
 
-    pub fn lt(&mut self, column: &str, value: serde_json::Value) -> TableSchema {
-        self.params
-            .push(("lt".to_string(), column.to_string(), value.to_string()));
-        self.clone()
+    pub fn neq(
+        &mut self,
+        column: impl AsRef<str>,
+        value: impl Into<serde_json::Value>,
+    ) -> TableSchema {
+        self.filter(Filter::neq(column.as_ref(), value))
     }
 
-    pub fn lte(&mut self, column: &str, value: serde_json::Value) -> TableSchema {
-        self.params
-            .push(("lte".to_string(), column.to_string(), value.to_string()));
-        self.clone()
+    pub fn gt(
+        &mut self,
+        column: impl AsRef<str>,
+        value: impl Into<serde_json::Value>,
+    ) -> TableSchema {
+        self.filter(Filter::gt(column.as_ref(), value))
     }
 
-    pub fn like(&mut self, column: &str, value: String) -> TableSchema {
-        self.params.push((
-            "like".to_string(),
-            column.to_string(),
-            value.replace("%", "*"),
-        ));
-        self.clone()
+    pub fn gte(
+        &mut self,
+        column: impl AsRef<str>,
+        value: impl Into<serde_json::Value>,
+    ) -> TableSchema {
+        self.filter(Filter::gte(column.as_ref(), value))
     }
 
-    pub fn ilike(&mut self, column: &str, value: String) -> TableSchema {
-        self.params.push((
-            "ilike".to_string(),
-            column.to_string(),
-            value.replace("%", "*"),
-        ));
-        self.clone()
+    pub fn lt(
+        &mut self,
+        column: impl AsRef<str>,
+        value: impl Into<serde_json::Value>,
+    ) -> TableSchema {
+        self.filter(Filter::lt(column.as_ref(), value))
     }
 
-    pub fn is(&mut self, column: &str, value: serde_json::Value) -> TableSchema {
-        self.params
-            .push(("is".to_string(), column.to_string(), value.to_string()));
-        self.clone()
+    pub fn lte(
+        &mut self,
+        column: impl AsRef<str>,
+        value: impl Into<serde_json::Value>,
+    ) -> TableSchema {
+        self.filter(Filter::lte(column.as_ref(), value))
     }
 
-    pub fn in_a(&mut self, column: &str, value: serde_json::Value) -> TableSchema {
-        let mut mapped_value = "(".to_string();
-        match value.as_array() {
-            Some(arr) => {
-                for v in arr {
-                    mapped_value += &format!("{},", v);
-                }
-                if !arr.is_empty() {
-                    mapped_value.pop();
-                }
-            }
-            None => panic!("Provide valid array"),
-        }
-        mapped_value += ")";
+    pub fn like(&mut self, column: impl AsRef<str>, value: String) -> TableSchema {
+        self.filter(Filter::like(column.as_ref(), &value))
+    }
 
-        self.params
-            .push(("in".to_string(), column.to_string(), mapped_value));
-        self.clone()
+    pub fn ilike(&mut self, column: impl AsRef<str>, value: String) -> TableSchema {
+        self.filter(Filter::ilike(column.as_ref(), &value))
     }
 
-    pub fn contains(&mut self, column: &str, value: serde_json::Value) -> TableSchema {
-        let mut mapped_value = "{".to_string();
-        match value.as_array() {
-            Some(arr) => {
-                for v in arr {
-                    mapped_value += &format!("{},", v);
-                }
-                if !arr.is_empty() {
-                    mapped_value.pop();
-                }
-            }
-            None => panic!("Provide valid array"),
+    pub fn is(
+        &mut self,
+        column: impl AsRef<str>,
+        value: impl Into<serde_json::Value>,
+    ) -> TableSchema {
+        self.filter(Filter::is(column.as_ref(), value))
+    }
+
+    pub fn in_a<I, V>(&mut self, column: impl AsRef<str>, values: I) -> TableSchema
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<serde_json::Value>,
+    {
+        self.filter(Filter::in_a(column.as_ref(), values))
+    }
+
+    pub fn contains<I, V>(&mut self, column: impl AsRef<str>, values: I) -> TableSchema
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<serde_json::Value>,
+    {
+        self.filter(Filter::contains(column.as_ref(), values))
+    }
+
+    pub fn contained_by<I, V>(&mut self, column: impl AsRef<str>, values: I) -> TableSchema
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<serde_json::Value>,
+    {
+        self.filter(Filter::contained_by(column.as_ref(), values))
+    }
+
+    pub fn is_distinct(
+        &mut self,
+        column: impl AsRef<str>,
+        value: impl Into<serde_json::Value>,
+    ) -> TableSchema {
+        self.filter(Filter::is_distinct(column.as_ref(), value))
+    }
+
+    pub fn overlaps<I, V>(&mut self, column: impl AsRef<str>, values: I) -> TableSchema
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<serde_json::Value>,
+    {
+        self.filter(Filter::overlaps(column.as_ref(), values))
+    }
+
+    pub fn range_lt(&mut self, column: impl AsRef<str>, range: &str) -> TableSchema {
+        self.filter(Filter::range_lt(column.as_ref(), range))
+    }
+
+    pub fn range_gt(&mut self, column: impl AsRef<str>, range: &str) -> TableSchema {
+        self.filter(Filter::range_gt(column.as_ref(), range))
+    }
+
+    pub fn range_lte(&mut self, column: impl AsRef<str>, range: &str) -> TableSchema {
+        self.filter(Filter::range_lte(column.as_ref(), range))
+    }
+
+    pub fn range_gte(&mut self, column: impl AsRef<str>, range: &str) -> TableSchema {
+        self.filter(Filter::range_gte(column.as_ref(), range))
+    }
+
+    pub fn range_adjacent(&mut self, column: impl AsRef<str>, range: &str) -> TableSchema {
+        self.filter(Filter::range_adjacent(column.as_ref(), range))
+    }
+
+    pub fn range_overlaps(&mut self, column: impl AsRef<str>, range: &str) -> TableSchema {
+        self.filter(Filter::range_overlaps(column.as_ref(), range))
+    }
+
+    pub fn like_all<I, S>(&mut self, column: impl AsRef<str>, patterns: I) -> TableSchema
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.filter(Filter::like_all(column.as_ref(), patterns))
+    }
+
+    pub fn like_any<I, S>(&mut self, column: impl AsRef<str>, patterns: I) -> TableSchema
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.filter(Filter::like_any(column.as_ref(), patterns))
+    }
+
+    pub fn ilike_all<I, S>(&mut self, column: impl AsRef<str>, patterns: I) -> TableSchema
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.filter(Filter::ilike_all(column.as_ref(), patterns))
+    }
+
+    pub fn ilike_any<I, S>(&mut self, column: impl AsRef<str>, patterns: I) -> TableSchema
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.filter(Filter::ilike_any(column.as_ref(), patterns))
+    }
+
+    pub fn regex_match(&mut self, column: impl AsRef<str>, pattern: &str) -> TableSchema {
+        self.filter(Filter::regex_match(column.as_ref(), pattern))
+    }
+
+    pub fn regex_imatch(&mut self, column: impl AsRef<str>, pattern: &str) -> TableSchema {
+        self.filter(Filter::regex_imatch(column.as_ref(), pattern))
+    }
+
+    pub fn fts(
+        &mut self,
+        column: impl AsRef<str>,
+        query: &str,
+        config: Option<&str>,
+    ) -> TableSchema {
+        self.filter(Filter::fts(column.as_ref(), query, config))
+    }
+
+    pub fn plfts(
+        &mut self,
+        column: impl AsRef<str>,
+        query: &str,
+        config: Option<&str>,
+    ) -> TableSchema {
+        self.filter(Filter::plfts(column.as_ref(), query, config))
+    }
+
+    pub fn phfts(
+        &mut self,
+        column: impl AsRef<str>,
+        query: &str,
+        config: Option<&str>,
+    ) -> TableSchema {
+        self.filter(Filter::phfts(column.as_ref(), query, config))
+    }
+
+    pub fn wfts(
+        &mut self,
+        column: impl AsRef<str>,
+        query: &str,
+        config: Option<&str>,
+    ) -> TableSchema {
+        self.filter(Filter::wfts(column.as_ref(), query, config))
+    }
+
+    // an eq filter for every column and value pair
+    pub fn matches<I, K, V>(&mut self, values: I) -> TableSchema
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: Into<serde_json::Value>,
+    {
+        for (column, value) in values {
+            self.filters.push(Filter::eq(column.as_ref(), value));
         }
-        mapped_value += "}";
+        self.clone()
+    }
+
+    pub fn not(&mut self, filter: Filter) -> TableSchema {
+        self.filter(!filter)
+    }
+
+    pub fn or<I>(&mut self, filters: I) -> TableSchema
+    where
+        I: IntoIterator<Item = Filter>,
+    {
+        self.filter(Filter::or(filters))
+    }
+
+    pub fn and<I>(&mut self, filters: I) -> TableSchema
+    where
+        I: IntoIterator<Item = Filter>,
+    {
+        self.filter(Filter::and(filters))
+    }
+
+    pub fn order(&mut self, column: &str, ascending: bool, nulls_first: bool) -> TableSchema {
+        self.push_order("order", column, ascending, nulls_first)
+    }
+
+    // orders the rows of an embedded resource
+    pub fn order_referenced(
+        &mut self,
+        table: &str,
+        column: &str,
+        ascending: bool,
+        nulls_first: bool,
+    ) -> TableSchema {
+        self.push_order(&format!("{}.order", table), column, ascending, nulls_first)
+    }
 
-        self.params
-            .push(("cs".to_string(), column.to_string(), mapped_value));
+    // filters the rows of an embedded resource rather than the parent rows
+    pub fn filter_referenced(&mut self, table: &str, filter: Filter) -> TableSchema {
+        let (key, value) = filter.to_param();
+        self.params.push((format!("{}.{}", table, key), value));
         self.clone()
     }
 
-    pub fn contained_by(&mut self, column: &str, value: serde_json::Value) -> TableSchema {
-        let mut mapped_value = "{".to_string();
-        match value.as_array() {
-            Some(arr) => {
-                for v in arr {
-                    mapped_value += &format!("{},", v);
-                }
-                if !arr.is_empty() {
-                    mapped_value.pop();
-                }
+    pub fn limit_referenced(&mut self, table: &str, count: u64) -> TableSchema {
+        self.set_param(&format!("{}.limit", table), count.to_string())
+    }
+
+    pub fn offset_referenced(&mut self, table: &str, count: u64) -> TableSchema {
+        self.set_param(&format!("{}.offset", table), count.to_string())
+    }
+
+    fn push_order(
+        &mut self,
+        key: &str,
+        column: &str,
+        ascending: bool,
+        nulls_first: bool,
+    ) -> TableSchema {
+        let value = format!(
+            "{}.{}.{}",
+            column,
+            if ascending { "asc" } else { "desc" },
+            if nulls_first {
+                "nullsfirst"
+            } else {
+                "nullslast"
+            }
+        );
+
+        // later calls add tie breakers to the same order parameter
+        match self.params.iter_mut().find(|(k, _)| k == key) {
+            Some((_, order)) => {
+                order.push(',');
+                order.push_str(&value);
             }
-            None => panic!("Provide valid array"),
+            None => self.params.push((key.to_string(), value)),
         }
-        mapped_value += "}";
+        self.clone()
+    }
 
-        self.params
-            .push(("cd".to_string(), column.to_string(), mapped_value));
+    pub fn limit(&mut self, count: u64) -> TableSchema {
+        self.set_param("limit", count.to_string())
+    }
+
+    pub fn offset(&mut self, count: u64) -> TableSchema {
+        self.set_param("offset", count.to_string())
+    }
+
+    fn set_param(&mut self, key: &str, value: String) -> TableSchema {
+        self.params.retain(|(k, _)| k != key);
+        self.params.push((key.to_string(), value));
         self.clone()
     }
 
-    pub fn not(&mut self, column: &str, rule: &str, value: serde_json::Value) -> TableSchema {
-        self.params.push((
-            format!("not.{}", rule),
-            column.to_string(),
-            value.to_string(),
-        ));
+    // rows `from` to `to`, both inclusive and starting at 0
+    pub fn range(&mut self, from: u64, to: u64) -> TableSchema {
+        self.range = Some((from, to));
         self.clone()
     }
 
-    pub fn or(&mut self, value: serde_json::Value) -> TableSchema {
-        let mut mapped_value = "(".to_string();
-        match value.as_array() {
-            Some(arr) => {
-                for v in arr {
-                    mapped_value += &format!("{},", v);
-                }
-                if !arr.is_empty() {
-                    mapped_value.pop();
-                }
-            }
-            None => panic!("Provide valid array"),
-        }
-        mapped_value += ")";
+    // asks postgrest for the total row count, returned in `Response::count`
+    pub fn count(&mut self, count: CountMode) -> TableSchema {
+        self.count = Some(count);
+        self.clone()
+    }
 
-        self.params
-            .push(("or".to_string(), "".to_string(), mapped_value));
+    // sends a HEAD request, so only the count comes back
+    pub fn head(&mut self) -> TableSchema {
+        self.head = true;
         self.clone()
     }
 
@@ -187,6 +604,34 @@ impl TableSchema {
         self.clone()
     }
 
+    // inserts rows, resolving primary key or `on_conflict` clashes by merging
+    pub fn upsert(&mut self, data: serde_json::Value) -> TableSchema {
+        self.insert = Some(data);
+        self.upsert = true;
+        self.clone()
+    }
+
+    // comma separated columns of the unique constraint an upsert resolves on
+    pub fn on_conflict(&mut self, columns: &str) -> TableSchema {
+        self.set_param("on_conflict", columns.to_string())
+    }
+
+    // keeps existing rows instead of merging them on upsert
+    pub fn ignore_duplicates(&mut self) -> TableSchema {
+        self.ignore_duplicates = true;
+        self.clone()
+    }
+
+    pub fn default_to_null(&mut self, default_to_null: bool) -> TableSchema {
+        self.default_to_null = default_to_null;
+        self.clone()
+    }
+
+    pub fn returning(&mut self, returning: Returning) -> TableSchema {
+        self.returning = Some(returning);
+        self.clone()
+    }
+
     pub fn delete(&mut self) -> TableSchema {
         self.delete = true;
         self.clone()
@@ -197,61 +642,479 @@ impl TableSchema {
         self.clone()
     }
 
+    // lets the retry policy apply to writes that are safe to repeat
+    pub fn idempotent(&mut self) -> TableSchema {
+        self.idempotent = true;
+        self.clone()
+    }
+
     pub async fn execute<T>(&self) -> Result<Response<T>, Error>
     where
         T: DeserializeOwned,
     {
-        let mut url = format!("rest/v1/{}", self.name);
+        self.fetch(None).await?.json()
+    }
 
-        let mut cmd = "?";
-        for (rule, column, value) in &self.params {
-            if rule == "select" {
-                url += &format!("{}{}={}", cmd, rule, column);
-            } else if rule == "or" {
-                url += &format!("{}{}={}", cmd, rule, value.replace("\"", ""));
-            } else {
-                url += &format!("{}{}={}.{}", cmd, column, rule, value.replace("\"", ""));
-            }
+    // exactly one row, anything else is `Error::NoRows` or `Error::MultipleRows`
+    pub async fn single<T>(&self) -> Result<Response<T>, Error>
+    where
+        T: DeserializeOwned,
+    {
+        self.fetch(Some(HeaderValue::from_static(OBJECT)))
+            .await?
+            .json()
+    }
 
-            cmd = "&";
+    // at most one row, no rows comes back as `data: None`
+    pub async fn maybe_single<T>(&self) -> Result<Response<T>, Error>
+    where
+        T: DeserializeOwned,
+    {
+        match self.fetch(Some(HeaderValue::from_static(OBJECT))).await {
+            Err(Error::NoRows { .. }) => Ok(Response {
+                code: 200,
+                data: None,
+                count: None,
+            }),
+            res => res?.json(),
         }
+    }
 
-        let mut body: serde_json::Value = json!({});
-        let method = if self.insert.is_some() {
-            body = self.insert.clone().unwrap();
-            Method::POST
-        } else if self.delete {
-            Method::DELETE
-        } else if self.update.is_some() {
-            body = self.update.clone().unwrap();
-            Method::PATCH
-        } else {
-            Method::GET
-        };
+    pub async fn csv(&self) -> Result<Response<String>, Error> {
+        self.fetch(Some(HeaderValue::from_static("text/csv"))).await
+    }
+
+    pub async fn geojson<T>(&self) -> Result<Response<T>, Error>
+    where
+        T: DeserializeOwned,
+    {
+        self.fetch(Some(HeaderValue::from_static("application/geo+json")))
+            .await?
+            .json()
+    }
 
+    // the execution plan postgres would use for this query, which needs
+    // `db-plan-enabled` on the postgrest side
+    pub async fn explain(&self, options: ExplainOptions) -> Result<Response<String>, Error> {
+        let accept =
+            HeaderValue::from_str(&options.media_type()).map_err(|_| Error::InvalidArgument {
+                message: "invalid explain options".to_string(),
+            })?;
+        self.fetch(Some(accept)).await
+    }
+
+    // sends the query and returns the raw body, which is empty in head mode
+    async fn fetch(&self, accept: Option<HeaderValue>) -> Result<Response<String>, Error> {
+        let (method, url, body, options) = self.request(accept)?;
         let res = self
             .client
-            .request(
-                method,
-                &url,
-                body,
-                Some(&self.client.access_token.clone().unwrap_or("".to_owned())),
-                None,
-            )
+            .authed_send(method, &url, body, &options)
             .await?;
 
-        let txt = res.data.unwrap();
-        let data = if txt.is_empty() {
-            json!({})
-        } else {
-            serde_json::from_str(&txt).unwrap()
-        };
+        let code = res.status().as_u16();
+        let count = res
+            .headers()
+            .get(CONTENT_RANGE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_total);
 
-        let t: T = serde_json::from_value(data).unwrap();
+        // minimal and headers-only mutations come back without a body, which
+        // decodes to no data
+        let txt = res.text().await?;
+        if !(200..300).contains(&code) {
+            return Err(single_row_error(Error::from_response(&url, code, &txt)));
+        }
 
         Ok(Response {
-            code: res.code,
-            data: Some(t),
+            code,
+            data: Some(txt),
+            count,
         })
     }
+
+    // the method, path, body and headers the query is sent with
+    fn request(
+        &self,
+        accept: Option<HeaderValue>,
+    ) -> Result<(Method, String, Body, RequestOptions), Error> {
+        if let Some(message) = &self.invalid {
+            return Err(Error::InvalidArgument {
+                message: message.clone(),
+            });
+        }
+
+        let mut query = self.params.clone();
+        query.extend(self.filters.iter().map(Filter::to_param));
+
+        // rows of a bulk insert may have different keys, so tell postgrest
+        // every column that appears in any of them
+        if let Some(serde_json::Value::Array(rows)) = &self.insert {
+            let mut columns: Vec<&str> = vec![];
+            for key in rows
+                .iter()
+                .filter_map(|row| row.as_object())
+                .flat_map(|row| row.keys())
+            {
+                if !columns.contains(&key.as_str()) {
+                    columns.push(key);
+                }
+            }
+
+            if !columns.is_empty() {
+                let columns = columns
+                    .iter()
+                    .map(|c| format!("\"{}\"", c))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                query.push(("columns".to_string(), columns));
+            }
+        }
+
+        let mut url = format!("rest/v1/{}", self.name);
+        for (i, (key, value)) in query.iter().enumerate() {
+            let cmd = if i == 0 { "?" } else { "&" };
+            url += &format!("{}{}={}", cmd, encode(key), encode(value));
+        }
+
+        let (method, body) = if let Some(args) = &self.rpc {
+            (Method::POST, Body::Json(args.clone()))
+        } else if let Some(data) = &self.insert {
+            (Method::POST, Body::Json(data.clone()))
+        } else if self.delete {
+            (Method::DELETE, Body::Empty)
+        } else if let Some(data) = &self.update {
+            (Method::PATCH, Body::Json(data.clone()))
+        } else if self.head {
+            (Method::HEAD, Body::Empty)
+        } else {
+            (Method::GET, Body::Empty)
+        };
+
+        let mut options = RequestOptions {
+            idempotent: self.idempotent,
+            ..RequestOptions::default()
+        };
+
+        let mut prefer = vec![];
+        let mutation = self.insert.is_some() || self.update.is_some() || self.delete;
+        if mutation {
+            let selected = self.params.iter().any(|(key, _)| key == "select");
+            let returning = self.returning.unwrap_or(if selected {
+                Returning::Representation
+            } else {
+                Returning::Minimal
+            });
+            prefer.push(returning.as_str().to_string());
+        }
+        if self.upsert {
+            prefer.push(if self.ignore_duplicates {
+                "resolution=ignore-duplicates".to_string()
+            } else {
+                "resolution=merge-duplicates".to_string()
+            });
+        }
+        if self.insert.is_some() && !self.default_to_null {
+            prefer.push("missing=default".to_string());
+        }
+        if let Some(count) = self.count {
+            prefer.push(format!("count={}", count.as_str()));
+        }
+        if let Ok(value) = HeaderValue::from_str(&prefer.join(","))
+            && !prefer.is_empty()
+        {
+            options.headers.insert("Prefer", value);
+        }
+
+        if let Some((from, to)) = self.range
+            && let Ok(value) = HeaderValue::from_str(&format!("{}-{}", from, to))
+        {
+            options
+                .headers
+                .insert("Range-Unit", HeaderValue::from_static("items"));
+            options.headers.insert(RANGE, value);
+        }
+
+        if let Some(accept) = accept {
+            options.headers.insert(ACCEPT, accept);
+        }
+
+        if let Some(schema) = &self.schema {
+            let profile = match method {
+                Method::GET | Method::HEAD => "Accept-Profile",
+                _ => "Content-Profile",
+            };
+            let schema = HeaderValue::from_str(schema).map_err(|_| Error::InvalidArgument {
+                message: format!("invalid schema: {}", schema),
+            })?;
+            options.headers.insert(profile, schema);
+        }
+
+        Ok((method, url, body, options))
+    }
+}
+
+// postgrest reports a single object request that matched zero or several
+// rows as PGRST116, with the row count in the details
+fn single_row_error(e: Error) -> Error {
+    match e {
+        Error::Postgrest {
+            status,
+            code: Some(code),
+            details,
+            ..
+        } if code == "PGRST116" => {
+            let rows = details
+                .as_deref()
+                .and_then(|d| d.split_whitespace().find_map(|w| w.parse::<u64>().ok()));
+            match rows {
+                Some(0) => Error::NoRows { status },
+                rows => Error::MultipleRows { status, rows },
+            }
+        }
+        e => e,
+    }
+}
+
+// the total from a `Content-Range` like `0-24/3573`, `*` when unknown
+fn parse_total(content_range: &str) -> Option<u64> {
+    content_range.rsplit_once('/')?.1.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::test_server::{Reply, Server};
+
+    fn pgrst116(details: Option<&str>) -> Error {
+        Error::Postgrest {
+            status: 406,
+            code: Some("PGRST116".to_string()),
+            message: "JSON object requested, multiple (or no) rows returned".to_string(),
+            details: details.map(String::from),
+            hint: None,
+        }
+    }
+
+    // the method, path and Prefer header a query is sent with
+    fn prepared(query: TableSchema) -> (Method, String, Option<String>) {
+        let (method, url, _, options) = query.request(None).unwrap();
+        let prefer = options
+            .headers
+            .get("Prefer")
+            .map(|v| v.to_str().unwrap().to_string());
+        (method, url, prefer)
+    }
+
+    #[test]
+    fn builds_insert_and_upsert_requests() {
+        let client = SupabaseClient::new();
+        let cases = [
+            (
+                client
+                    .from("t")
+                    .upsert(json!({ "a": 1 }))
+                    .ignore_duplicates()
+                    .select("*"),
+                "rest/v1/t?select=%2A",
+                "return=representation,resolution=ignore-duplicates",
+            ),
+            (
+                client.from("t").upsert(json!({ "a": 1 })).on_conflict("a"),
+                "rest/v1/t?on_conflict=a",
+                "return=minimal,resolution=merge-duplicates",
+            ),
+            // columns="a","b"
+            (
+                client
+                    .from("t")
+                    .insert(json!([{ "a": 1 }, { "b": 2, "a": 3 }])),
+                "rest/v1/t?columns=%22a%22%2C%22b%22",
+                "return=minimal",
+            ),
+            (
+                client
+                    .from("t")
+                    .insert(json!([{ "a": 1 }]))
+                    .default_to_null(false),
+                "rest/v1/t?columns=%22a%22",
+                "return=minimal,missing=default",
+            ),
+        ];
+
+        for (query, url, prefer) in cases {
+            let expected = (Method::POST, url.to_string(), Some(prefer.to_string()));
+            assert_eq!(prepared(query), expected);
+        }
+    }
+
+    #[test]
+    fn picks_what_mutations_return() {
+        let client = SupabaseClient::new();
+        let cases = [
+            (client.from("t").select("a"), Method::GET, None),
+            (
+                client.from("t").update(json!({ "a": 1 })),
+                Method::PATCH,
+                Some("return=minimal"),
+            ),
+            (
+                client.from("t").update(json!({ "a": 1 })).select("a"),
+                Method::PATCH,
+                Some("return=representation"),
+            ),
+            (
+                client
+                    .from("t")
+                    .insert(json!({ "a": 1 }))
+                    .select("a")
+                    .returning(Returning::Minimal),
+                Method::POST,
+                Some("return=minimal"),
+            ),
+            (
+                client.from("t").delete().returning(Returning::HeadersOnly),
+                Method::DELETE,
+                Some("return=headers-only"),
+            ),
+            (
+                client.from("t").delete().count(CountMode::Exact),
+                Method::DELETE,
+                Some("return=minimal,count=exact"),
+            ),
+        ];
+
+        for (query, method, prefer) in cases {
+            let (sent, _, sent_prefer) = prepared(query);
+            assert_eq!((sent, sent_prefer.as_deref()), (method, prefer));
+        }
+    }
+
+    #[test]
+    fn sends_the_schema_as_a_profile_header() {
+        let client = SupabaseClient::new();
+        let app = client.clone().schema("app");
+        let cases = [
+            (client.from("t").schema("app").select("a"), "Accept-Profile"),
+            (client.from("t").schema("app").head(), "Accept-Profile"),
+            (client.from("t").schema("app").delete(), "Content-Profile"),
+            (
+                client.from("t").schema("app").insert(json!({ "a": 1 })),
+                "Content-Profile",
+            ),
+            (
+                client.from("t").schema("app").update(json!({ "a": 1 })),
+                "Content-Profile",
+            ),
+            (client.rpc("f", json!({})).schema("app"), "Content-Profile"),
+            // the client's schema applies unless the query overrides it
+            (app.from("t").select("a"), "Accept-Profile"),
+            (app.from("t").delete(), "Content-Profile"),
+        ];
+
+        for (query, header) in cases {
+            let (_, _, _, options) = query.request(None).unwrap();
+            let profiles: Vec<_> = ["Accept-Profile", "Content-Profile"]
+                .into_iter()
+                .filter_map(|name| Some((name, options.headers.get(name)?.to_str().ok()?)))
+                .collect();
+            assert_eq!(profiles, [(header, "app")]);
+        }
+
+        let (_, _, _, options) = client.from("t").select("a").request(None).unwrap();
+        assert!(options.headers.get("Accept-Profile").is_none());
+    }
+
+    #[tokio::test]
+    async fn rejects_non_object_rpc_args_in_the_url() {
+        let server = Server::start(|_| Reply::json(200, json!([]))).await;
+        let client = server.client();
+        let get = || RpcOptions {
+            get: true,
+            ..RpcOptions::default()
+        };
+
+        let res = client
+            .rpc_with("f", json!([1, 2]), get())
+            .execute::<Value>()
+            .await;
+        assert!(matches!(res, Err(Error::InvalidArgument { .. })));
+        let head = RpcOptions {
+            head: true,
+            ..RpcOptions::default()
+        };
+        let res = client
+            .rpc_with("f", json!("x"), head)
+            .execute::<Value>()
+            .await;
+        assert!(matches!(res, Err(Error::InvalidArgument { .. })));
+        assert!(server.requests().is_empty());
+
+        client
+            .rpc_with("f", json!(null), get())
+            .execute::<Value>()
+            .await
+            .unwrap();
+        client
+            .rpc_with("f", json!({ "a": 1, "b": [1, 2] }), get())
+            .execute::<Value>()
+            .await
+            .unwrap();
+        let paths: Vec<String> = server.requests().into_iter().map(|r| r.path).collect();
+        assert_eq!(paths, ["rest/v1/rpc/f", "rest/v1/rpc/f?a=1&b=%7B1%2C2%7D"]);
+    }
+
+    #[test]
+    fn maps_single_row_errors() {
+        assert!(matches!(
+            single_row_error(pgrst116(Some("The result contains 0 rows"))),
+            Error::NoRows { status: 406 }
+        ));
+
+        let cases = [
+            (
+                Some("Results contain 2 rows, application/vnd.pgrst.object+json requires 1 row"),
+                Some(2),
+            ),
+            (Some("The result contains many rows"), None),
+            (None, None),
+        ];
+
+        for (details, expected) in cases {
+            match single_row_error(pgrst116(details)) {
+                Error::MultipleRows { status: 406, rows } => assert_eq!(rows, expected),
+                e => panic!("{:?} mapped to {:?}", details, e),
+            }
+        }
+
+        // other postgrest errors pass through untouched
+        let other = Error::Postgrest {
+            status: 400,
+            code: Some("PGRST100".to_string()),
+            message: "parse error".to_string(),
+            details: None,
+            hint: None,
+        };
+        assert!(matches!(
+            single_row_error(other),
+            Error::Postgrest { code: Some(code), .. } if code == "PGRST100"
+        ));
+    }
+
+    #[test]
+    fn parses_content_range_total() {
+        let cases = [
+            ("0-24/3573", Some(3573)),
+            ("*/0", Some(0)),
+            ("0-9/*", None),
+            ("*/*", None),
+            ("0-9", None),
+            ("", None),
+        ];
+
+        for (content_range, expected) in cases {
+            assert_eq!(parse_total(content_range), expected, "{:?}", content_range);
+        }
+    }
 }
@@ -0,0 +1,1237 @@
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures_util::{SinkExt, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tokio::{
+    net::TcpStream,
+    sync::{broadcast, mpsc, watch},
+};
+use tokio_tungstenite::{
+    MaybeTlsStream, WebSocketStream, connect_async, tungstenite::Message as WsMessage,
+};
+
+use crate::{
+    client::{Error, SupabaseClient},
+    session::AuthChange,
+};
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(25);
+const JOIN_TIMEOUT: Duration = Duration::from_secs(10);
+const RECONNECT_DELAYS: [Duration; 4] = [
+    Duration::from_secs(1),
+    Duration::from_secs(2),
+    Duration::from_secs(5),
+    Duration::from_secs(10),
+];
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// a phoenix channel message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Message {
+    pub topic: String,
+    pub event: String,
+    pub payload: Value,
+    #[serde(rename = "ref")]
+    pub msg_ref: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub join_ref: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PostgresChangeEvent {
+    #[serde(rename = "*")]
+    All,
+    #[serde(rename = "INSERT")]
+    Insert,
+    #[serde(rename = "UPDATE")]
+    Update,
+    #[serde(rename = "DELETE")]
+    Delete,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PostgresChangesFilter {
+    pub event: PostgresChangeEvent,
+    pub schema: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub table: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter: Option<String>,
+}
+
+impl PostgresChangesFilter {
+    pub fn new(schema: &str) -> Self {
+        Self {
+            event: PostgresChangeEvent::All,
+            schema: schema.to_string(),
+            table: None,
+            filter: None,
+        }
+    }
+
+    pub fn event(self, event: PostgresChangeEvent) -> Self {
+        Self { event, ..self }
+    }
+
+    pub fn table(self, table: &str) -> Self {
+        Self {
+            table: Some(table.to_string()),
+            ..self
+        }
+    }
+
+    // a postgrest style filter such as `id=eq.1`
+    pub fn filter(self, filter: &str) -> Self {
+        Self {
+            filter: Some(filter.to_string()),
+            ..self
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PostgresChangePayload {
+    pub schema: String,
+    pub table: String,
+    pub commit_timestamp: String,
+    #[serde(rename = "type")]
+    pub event_type: PostgresChangeEvent,
+    #[serde(default)]
+    pub record: Option<Value>,
+    #[serde(default)]
+    pub old_record: Option<Value>,
+    #[serde(default)]
+    pub errors: Option<Value>,
+}
+
+impl PostgresChangePayload {
+    pub fn record_as<T>(&self) -> Result<Option<T>, serde_json::Error>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        self.record.clone().map(serde_json::from_value).transpose()
+    }
+
+    pub fn old_record_as<T>(&self) -> Result<Option<T>, serde_json::Error>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        self.old_record
+            .clone()
+            .map(serde_json::from_value)
+            .transpose()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChannelStatus {
+    Closed,
+    Joining,
+    Joined,
+    Errored(String),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ChannelConfig {
+    // receive our own broadcasts back
+    pub broadcast_self: bool,
+    // have the server acknowledge each broadcast
+    pub broadcast_ack: bool,
+    // identifies this client in presence, the server picks one when empty
+    pub presence_key: String,
+    // requires realtime authorization policies to join
+    pub private: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BroadcastMessage {
+    pub event: String,
+    pub payload: Value,
+}
+
+// presence keys mapped to the metas each of their connections tracked
+pub type PresenceState = HashMap<String, Vec<Value>>;
+
+#[derive(Debug, Clone)]
+pub enum PresenceEvent {
+    Sync(PresenceState),
+    Join {
+        key: String,
+        current: Vec<Value>,
+        joined: Vec<Value>,
+    },
+    Leave {
+        key: String,
+        current: Vec<Value>,
+        left: Vec<Value>,
+    },
+}
+
+pub struct Subscription<T> {
+    rx: mpsc::UnboundedReceiver<T>,
+    // keeps the socket alive for as long as someone is listening
+    _channel: RealtimeChannel,
+}
+
+impl<T> Stream for Subscription<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+pub type PostgresChanges = Subscription<PostgresChangePayload>;
+pub type Broadcasts = Subscription<BroadcastMessage>;
+pub type PresenceEvents = Subscription<PresenceEvent>;
+
+// one websocket shared by every channel created from it, keep it around and
+// reuse it instead of calling `SupabaseClient::realtime` per channel
+#[derive(Clone)]
+pub struct RealtimeClient {
+    shared: Arc<Shared>,
+    commands: mpsc::UnboundedSender<Command>,
+    // how long `subscribe` waits for the server to accept a join
+    timeout: Duration,
+}
+
+#[derive(Clone)]
+pub struct RealtimeChannel {
+    realtime: RealtimeClient,
+    pub topic: String,
+}
+
+struct Shared {
+    client: SupabaseClient,
+    channels: Mutex<HashMap<String, ChannelEntry>>,
+    next_ref: AtomicU64,
+    // taken by the socket task when it is started
+    receiver: Mutex<Option<mpsc::UnboundedReceiver<Command>>>,
+}
+
+struct ChannelEntry {
+    config: ChannelConfig,
+    postgres: Vec<PostgresBinding>,
+    broadcast: Vec<(String, mpsc::UnboundedSender<BroadcastMessage>)>,
+    presence: Vec<mpsc::UnboundedSender<PresenceEvent>>,
+    presence_state: PresenceState,
+    join_ref: Option<String>,
+    status: watch::Sender<ChannelStatus>,
+}
+
+struct PostgresBinding {
+    filter: PostgresChangesFilter,
+    id: Option<u64>,
+    tx: mpsc::UnboundedSender<PostgresChangePayload>,
+}
+
+enum Command {
+    Join(String),
+    Leave(String),
+    Push {
+        topic: String,
+        event: String,
+        payload: Value,
+    },
+    Disconnect,
+}
+
+enum Exit {
+    Dropped,
+    Shutdown,
+}
+
+impl SupabaseClient {
+    pub fn realtime(&self) -> RealtimeClient {
+        let (commands, receiver) = mpsc::unbounded_channel();
+
+        RealtimeClient {
+            shared: Arc::new(Shared {
+                client: self.clone(),
+                channels: Mutex::new(HashMap::new()),
+                next_ref: AtomicU64::new(1),
+                receiver: Mutex::new(Some(receiver)),
+            }),
+            commands,
+            timeout: JOIN_TIMEOUT,
+        }
+    }
+}
+
+impl RealtimeClient {
+    // applies to channels created after the call
+    pub fn timeout(self, timeout: Duration) -> Self {
+        Self { timeout, ..self }
+    }
+
+    pub fn channel(&self, name: &str) -> RealtimeChannel {
+        self.channel_with(name, ChannelConfig::default())
+    }
+
+    pub fn channel_with(&self, name: &str, config: ChannelConfig) -> RealtimeChannel {
+        let topic = format!("realtime:{}", name);
+
+        self.shared
+            .channels
+            .lock()
+            .unwrap()
+            .entry(topic.clone())
+            .or_insert_with(|| ChannelEntry {
+                config,
+                postgres: vec![],
+                broadcast: vec![],
+                presence: vec![],
+                presence_state: HashMap::new(),
+                join_ref: None,
+                status: watch::Sender::new(ChannelStatus::Closed),
+            });
+
+        RealtimeChannel {
+            realtime: self.clone(),
+            topic,
+        }
+    }
+
+    // starts the socket task, later calls are no-ops
+    pub fn connect(&self) {
+        if let Some(receiver) = self.shared.receiver.lock().unwrap().take() {
+            tokio::spawn(run(self.shared.clone(), receiver));
+        }
+    }
+
+    pub fn disconnect(&self) {
+        let _ = self.commands.send(Command::Disconnect);
+    }
+
+    fn send(&self, command: Command) -> Result<(), Error> {
+        self.commands.send(command).map_err(|_| Error::Realtime {
+            message: "realtime socket is closed".to_string(),
+        })
+    }
+}
+
+impl RealtimeChannel {
+    pub fn status(&self) -> ChannelStatus {
+        self.with_entry(|entry| entry.status.borrow().clone())
+            .unwrap_or(ChannelStatus::Closed)
+    }
+
+    // must be registered before `subscribe` so the binding is part of the join
+    pub fn on_postgres_changes(&self, filter: PostgresChangesFilter) -> PostgresChanges {
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        self.with_entry(|entry| {
+            entry.postgres.push(PostgresBinding {
+                filter,
+                id: None,
+                tx,
+            })
+        });
+
+        Subscription {
+            rx,
+            _channel: self.clone(),
+        }
+    }
+
+    // `event` may be `*` to receive every broadcast on the channel
+    pub fn on_broadcast(&self, event: &str) -> Broadcasts {
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        self.with_entry(|entry| entry.broadcast.push((event.to_string(), tx)));
+
+        Subscription {
+            rx,
+            _channel: self.clone(),
+        }
+    }
+
+    pub fn on_presence(&self) -> PresenceEvents {
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        self.with_entry(|entry| entry.presence.push(tx));
+
+        Subscription {
+            rx,
+            _channel: self.clone(),
+        }
+    }
+
+    pub fn presence_state(&self) -> PresenceState {
+        self.with_entry(|entry| entry.presence_state.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn send_broadcast(&self, event: &str, payload: Value) -> Result<(), Error> {
+        self.push(
+            "broadcast",
+            json!({
+                "type": "broadcast",
+                "event": event,
+                "payload": payload,
+            }),
+        )
+    }
+
+    pub fn track(&self, state: Value) -> Result<(), Error> {
+        self.push(
+            "presence",
+            json!({
+                "type": "presence",
+                "event": "track",
+                "payload": state,
+            }),
+        )
+    }
+
+    pub fn untrack(&self) -> Result<(), Error> {
+        self.push(
+            "presence",
+            json!({
+                "type": "presence",
+                "event": "untrack",
+            }),
+        )
+    }
+
+    fn push(&self, event: &str, payload: Value) -> Result<(), Error> {
+        self.realtime.send(Command::Push {
+            topic: self.topic.clone(),
+            event: event.to_string(),
+            payload,
+        })
+    }
+
+    pub async fn subscribe(&self) -> Result<(), Error> {
+        let mut status = self
+            .with_entry(|entry| {
+                entry.join_ref = None;
+                entry.status.send_replace(ChannelStatus::Joining);
+                entry.status.subscribe()
+            })
+            .ok_or_else(|| Error::Realtime {
+                message: format!("channel {} was removed", self.topic),
+            })?;
+
+        self.realtime.connect();
+        self.realtime.send(Command::Join(self.topic.clone()))?;
+
+        // the socket task keeps reconnecting to an unreachable server, so
+        // give up on the join rather than wait forever
+        let joined =
+            status.wait_for(|s| matches!(s, ChannelStatus::Joined | ChannelStatus::Errored(_)));
+        let status = tokio::time::timeout(self.realtime.timeout, joined)
+            .await
+            .map_err(|_| Error::Realtime {
+                message: format!("timed out joining {}", self.topic),
+            })?
+            .map_err(|_| Error::Realtime {
+                message: "realtime socket is closed".to_string(),
+            })?
+            .clone();
+
+        match status {
+            ChannelStatus::Errored(message) => Err(Error::Realtime { message }),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn unsubscribe(&self) -> Result<(), Error> {
+        self.realtime.send(Command::Leave(self.topic.clone()))
+    }
+
+    fn with_entry<R>(&self, f: impl FnOnce(&mut ChannelEntry) -> R) -> Option<R> {
+        self.realtime
+            .shared
+            .channels
+            .lock()
+            .unwrap()
+            .get_mut(&self.topic)
+            .map(f)
+    }
+}
+
+impl Shared {
+    fn next_ref(&self) -> String {
+        self.next_ref.fetch_add(1, Ordering::Relaxed).to_string()
+    }
+
+    fn endpoint(&self) -> String {
+        let base = self.client.base_url.trim_end_matches('/');
+        let base = if let Some(rest) = base.strip_prefix("https://") {
+            format!("wss://{}", rest)
+        } else if let Some(rest) = base.strip_prefix("http://") {
+            format!("ws://{}", rest)
+        } else {
+            base.to_string()
+        };
+
+        format!(
+            "{}/realtime/v1/websocket?apikey={}&vsn=1.0.0",
+            base, self.client.api_key
+        )
+    }
+
+    // the signed in user's token, falling back to the anon key. a rejected
+    // refresh token signs the user out, so the join goes out as anon, while
+    // a refresh that failed on the network sends the stale token
+    async fn access_token(&self) -> String {
+        self.client
+            .bearer()
+            .await
+            .unwrap_or_else(|_| self.client.access_token())
+            .unwrap_or_else(|| self.client.api_key.clone())
+    }
+
+    // builds the phx_join for a channel and remembers its ref so the reply
+    // can be matched
+    async fn join_message(&self, topic: &str) -> Option<Message> {
+        let access_token = self.access_token().await;
+        let join_ref = self.next_ref();
+
+        let mut channels = self.channels.lock().unwrap();
+        let entry = channels.get_mut(topic)?;
+        entry.join_ref = Some(join_ref.clone());
+        entry.presence_state.clear();
+        for binding in entry.postgres.iter_mut() {
+            binding.id = None;
+        }
+
+        let postgres: Vec<&PostgresChangesFilter> =
+            entry.postgres.iter().map(|b| &b.filter).collect();
+
+        Some(Message {
+            topic: topic.to_string(),
+            event: "phx_join".to_string(),
+            payload: json!({
+                "config": {
+                    "broadcast": {
+                        "ack": entry.config.broadcast_ack,
+                        "self": entry.config.broadcast_self,
+                    },
+                    "presence": { "key": entry.config.presence_key },
+                    "postgres_changes": postgres,
+                    "private": entry.config.private,
+                },
+                "access_token": access_token,
+            }),
+            msg_ref: Some(join_ref.clone()),
+            join_ref: Some(join_ref),
+        })
+    }
+
+    fn handle(&self, msg: Message) {
+        let mut channels = self.channels.lock().unwrap();
+        let Some(entry) = channels.get_mut(&msg.topic) else {
+            return;
+        };
+
+        match msg.event.as_str() {
+            "phx_reply" if msg.msg_ref.is_some() && msg.msg_ref == entry.join_ref => {
+                match msg.payload["status"].as_str() {
+                    Some("ok") => {
+                        let ids = msg.payload["response"]["postgres_changes"]
+                            .as_array()
+                            .cloned()
+                            .unwrap_or_default();
+                        for (binding, server) in entry.postgres.iter_mut().zip(ids) {
+                            binding.id = server["id"].as_u64();
+                        }
+                        entry.status.send_replace(ChannelStatus::Joined);
+                    }
+                    _ => {
+                        let reason = msg.payload["response"]["reason"]
+                            .as_str()
+                            .unwrap_or("join rejected")
+                            .to_string();
+                        entry.status.send_replace(ChannelStatus::Errored(reason));
+                    }
+                }
+            }
+            "phx_error" => {
+                entry
+                    .status
+                    .send_replace(ChannelStatus::Errored("channel crashed".to_string()));
+            }
+            "phx_close" => {
+                entry.status.send_replace(ChannelStatus::Closed);
+            }
+            "postgres_changes" => {
+                let ids: Vec<u64> = msg.payload["ids"]
+                    .as_array()
+                    .map(|ids| ids.iter().filter_map(Value::as_u64).collect())
+                    .unwrap_or_default();
+                let Ok(change) =
+                    serde_json::from_value::<PostgresChangePayload>(msg.payload["data"].clone())
+                else {
+                    return;
+                };
+
+                for binding in &entry.postgres {
+                    if binding.id.is_some_and(|id| ids.contains(&id)) {
+                        let _ = binding.tx.send(change.clone());
+                    }
+                }
+            }
+            "broadcast" => {
+                let Ok(broadcast) = serde_json::from_value::<BroadcastMessage>(msg.payload) else {
+                    return;
+                };
+
+                for (event, tx) in &entry.broadcast {
+                    if event == "*" || *event == broadcast.event {
+                        let _ = tx.send(broadcast.clone());
+                    }
+                }
+            }
+            "presence_state" => {
+                entry.presence_state = msg
+                    .payload
+                    .as_object()
+                    .map(|state| {
+                        state
+                            .iter()
+                            .map(|(key, value)| (key.clone(), metas(value)))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                entry.emit_presence(PresenceEvent::Sync(entry.presence_state.clone()));
+            }
+            "presence_diff" => {
+                entry.apply_presence_diff(&msg.payload);
+                entry.emit_presence(PresenceEvent::Sync(entry.presence_state.clone()));
+            }
+            _ => {}
+        }
+    }
+
+    // fills in the refs of a client push, None if the channel is gone
+    fn push_message(&self, topic: String, event: String, payload: Value) -> Option<Message> {
+        let join_ref = self.channels.lock().unwrap().get(&topic)?.join_ref.clone();
+
+        Some(Message {
+            topic,
+            event,
+            payload,
+            msg_ref: Some(self.next_ref()),
+            join_ref,
+        })
+    }
+
+    // tells every joined channel about a new token so authorized channels
+    // keep working after a refresh or `set_session`
+    fn access_token_messages(&self, access_token: &str) -> Vec<Message> {
+        let topics: Vec<String> = self
+            .channels
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, entry)| *entry.status.borrow() == ChannelStatus::Joined)
+            .map(|(topic, _)| topic.clone())
+            .collect();
+
+        topics
+            .into_iter()
+            .filter_map(|topic| {
+                self.push_message(
+                    topic,
+                    "access_token".to_string(),
+                    json!({ "access_token": access_token }),
+                )
+            })
+            .collect()
+    }
+
+    fn drop_joins(&self) {
+        for entry in self.channels.lock().unwrap().values_mut() {
+            entry.join_ref = None;
+            if *entry.status.borrow() == ChannelStatus::Joined {
+                entry.status.send_replace(ChannelStatus::Joining);
+            }
+        }
+    }
+
+    // false once a join for the topic went out on this socket, a join command
+    // queued before the socket opened can arrive after the reply, and
+    // rejoining would throw away the postgres ids it assigned
+    fn needs_join(&self, topic: &str) -> bool {
+        self.channels
+            .lock()
+            .unwrap()
+            .get(topic)
+            .is_some_and(|entry| entry.join_ref.is_none())
+    }
+
+    fn joining_topics(&self) -> Vec<String> {
+        self.channels
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, entry)| *entry.status.borrow() != ChannelStatus::Closed)
+            .map(|(topic, _)| topic.clone())
+            .collect()
+    }
+}
+
+impl ChannelEntry {
+    fn emit_presence(&self, event: PresenceEvent) {
+        for tx in &self.presence {
+            let _ = tx.send(event.clone());
+        }
+    }
+
+    fn apply_presence_diff(&mut self, diff: &Value) {
+        if let Some(joins) = diff["joins"].as_object() {
+            for (key, value) in joins {
+                let current = self.presence_state.get(key).cloned().unwrap_or_default();
+                let joined = metas(value);
+
+                let metas = self.presence_state.entry(key.clone()).or_default();
+                for meta in &joined {
+                    if !metas.iter().any(|m| m["phx_ref"] == meta["phx_ref"]) {
+                        metas.push(meta.clone());
+                    }
+                }
+
+                self.emit_presence(PresenceEvent::Join {
+                    key: key.clone(),
+                    current,
+                    joined,
+                });
+            }
+        }
+
+        if let Some(leaves) = diff["leaves"].as_object() {
+            for (key, value) in leaves {
+                let left = metas(value);
+                let Some(metas) = self.presence_state.get_mut(key) else {
+                    continue;
+                };
+
+                metas.retain(|m| !left.iter().any(|l| l["phx_ref"] == m["phx_ref"]));
+                let current = metas.clone();
+                if current.is_empty() {
+                    self.presence_state.remove(key);
+                }
+
+                self.emit_presence(PresenceEvent::Leave {
+                    key: key.clone(),
+                    current,
+                    left,
+                });
+            }
+        }
+    }
+}
+
+fn metas(presence: &Value) -> Vec<Value> {
+    presence["metas"].as_array().cloned().unwrap_or_default()
+}
+
+// owns the websocket and reconnects it with backoff until every handle is
+// dropped or `disconnect` is called
+async fn run(shared: Arc<Shared>, mut commands: mpsc::UnboundedReceiver<Command>) {
+    let mut auth = shared.client.on_auth_state_change();
+    // pushes made while disconnected, sent once the channels are rejoined
+    let mut buffer: Vec<Message> = vec![];
+    let mut attempt = 0;
+
+    loop {
+        if let Ok((socket, _)) = connect_async(shared.endpoint()).await {
+            attempt = 0;
+            let exit = session(&shared, socket, &mut commands, &mut auth, &mut buffer).await;
+            if let Exit::Shutdown = exit {
+                return;
+            }
+        }
+
+        shared.drop_joins();
+
+        let delay = RECONNECT_DELAYS[attempt.min(RECONNECT_DELAYS.len() - 1)];
+        attempt += 1;
+
+        let sleep = tokio::time::sleep(delay);
+        tokio::pin!(sleep);
+        loop {
+            tokio::select! {
+                _ = &mut sleep => break,
+                command = commands.recv() => match command {
+                    None | Some(Command::Disconnect) => return,
+                    // joins are replayed once the socket is back
+                    Some(Command::Join(_)) => {}
+                    Some(Command::Leave(topic)) => {
+                        shared.channels.lock().unwrap().remove(&topic);
+                    }
+                    Some(Command::Push { topic, event, payload }) => {
+                        buffer.extend(shared.push_message(topic, event, payload));
+                    }
+                },
+            }
+        }
+    }
+}
+
+async fn session(
+    shared: &Shared,
+    socket: Socket,
+    commands: &mut mpsc::UnboundedReceiver<Command>,
+    auth: &mut broadcast::Receiver<AuthChange>,
+    buffer: &mut Vec<Message>,
+) -> Exit {
+    let (mut sink, mut stream) = socket.split();
+
+    for topic in shared.joining_topics() {
+        if let Some(join) = shared.join_message(&topic).await
+            && send(&mut sink, &join).await.is_err()
+        {
+            return Exit::Dropped;
+        }
+    }
+    while let Some(mut msg) = buffer.first().cloned() {
+        // refs of buffered pushes point at the join of the previous socket
+        msg.join_ref = shared
+            .channels
+            .lock()
+            .unwrap()
+            .get(&msg.topic)
+            .and_then(|entry| entry.join_ref.clone());
+        if send(&mut sink, &msg).await.is_err() {
+            return Exit::Dropped;
+        }
+        buffer.remove(0);
+    }
+
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+    heartbeat.tick().await;
+    let mut pending_heartbeat: Option<String> = None;
+
+    loop {
+        tokio::select! {
+            _ = heartbeat.tick() => {
+                // the previous heartbeat was never answered, the connection is dead
+                if pending_heartbeat.is_some() {
+                    return Exit::Dropped;
+                }
+                let heartbeat_ref = shared.next_ref();
+                let msg = Message {
+                    topic: "phoenix".to_string(),
+                    event: "heartbeat".to_string(),
+                    payload: json!({}),
+                    msg_ref: Some(heartbeat_ref.clone()),
+                    join_ref: None,
+                };
+                if send(&mut sink, &msg).await.is_err() {
+                    return Exit::Dropped;
+                }
+                pending_heartbeat = Some(heartbeat_ref);
+            }
+            command = commands.recv() => {
+                let msg = match command {
+                    None | Some(Command::Disconnect) => {
+                        let _ = sink.close().await;
+                        return Exit::Shutdown;
+                    }
+                    Some(Command::Join(topic)) if shared.needs_join(&topic) => {
+                        match shared.join_message(&topic).await {
+                            Some(join) => join,
+                            None => continue,
+                        }
+                    }
+                    Some(Command::Join(_)) => continue,
+                    Some(Command::Push { topic, event, payload }) => {
+                        match shared.push_message(topic, event, payload) {
+                            Some(msg) => msg,
+                            None => continue,
+                        }
+                    }
+                    Some(Command::Leave(topic)) => {
+                        let removed = shared.channels.lock().unwrap().remove(&topic);
+                        let Some(entry) = removed else { continue };
+                        entry.status.send_replace(ChannelStatus::Closed);
+                        Message {
+                            topic,
+                            event: "phx_leave".to_string(),
+                            payload: json!({}),
+                            msg_ref: Some(shared.next_ref()),
+                            join_ref: entry.join_ref,
+                        }
+                    }
+                };
+                if send(&mut sink, &msg).await.is_err() {
+                    if matches!(msg.event.as_str(), "broadcast" | "presence") {
+                        buffer.push(msg);
+                    }
+                    return Exit::Dropped;
+                }
+            }
+            // lagging behind is fine, only the latest token matters
+            _ = auth.recv() => {
+                let access_token = shared.access_token().await;
+                for msg in shared.access_token_messages(&access_token) {
+                    if send(&mut sink, &msg).await.is_err() {
+                        return Exit::Dropped;
+                    }
+                }
+            }
+            frame = stream.next() => match frame {
+                Some(Ok(WsMessage::Text(txt))) => {
+                    let Ok(msg) = serde_json::from_str::<Message>(&txt) else { continue };
+                    if msg.topic == "phoenix" {
+                        if msg.msg_ref.is_some() && msg.msg_ref == pending_heartbeat {
+                            pending_heartbeat = None;
+                        }
+                    } else {
+                        shared.handle(msg);
+                    }
+                }
+                Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => return Exit::Dropped,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+async fn send(
+    sink: &mut futures_util::stream::SplitSink<Socket, WsMessage>,
+    msg: &Message,
+) -> Result<(), tokio_tungstenite::tungstenite::Error> {
+    let txt = serde_json::to_string(msg).unwrap_or_default();
+    sink.send(WsMessage::Text(txt.into())).await
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::accept_async;
+
+    use super::*;
+
+    type ServerSocket = WebSocketStream<TcpStream>;
+
+    // a realtime client for a local websocket server, and the server side of
+    // every connection it makes
+    async fn ws_server() -> (RealtimeClient, mpsc::UnboundedReceiver<ServerSocket>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                if let Ok(ws) = accept_async(socket).await {
+                    let _ = tx.send(ws);
+                }
+            }
+        });
+
+        let client = SupabaseClient::new().base_url(&url).api_key("anon");
+        (client.realtime(), rx)
+    }
+
+    async fn recv(ws: &mut ServerSocket) -> Message {
+        loop {
+            if let WsMessage::Text(txt) = ws.next().await.unwrap().unwrap() {
+                return serde_json::from_str(&txt).unwrap();
+            }
+        }
+    }
+
+    async fn reply(ws: &mut ServerSocket, join: &Message, status: &str, response: Value) {
+        let msg = Message {
+            topic: join.topic.clone(),
+            event: "phx_reply".to_string(),
+            payload: json!({ "status": status, "response": response }),
+            msg_ref: join.msg_ref.clone(),
+            join_ref: join.join_ref.clone(),
+        };
+        let txt = serde_json::to_string(&msg).unwrap();
+        ws.send(WsMessage::Text(txt.into())).await.unwrap();
+    }
+
+    async fn change(ws: &mut ServerSocket, topic: &str, ids: &[u64], event: &str) {
+        let msg = Message {
+            topic: topic.to_string(),
+            event: "postgres_changes".to_string(),
+            payload: json!({
+                "ids": ids,
+                "data": {
+                    "schema": "public",
+                    "table": "todos",
+                    "commit_timestamp": "2024-01-01T00:00:00Z",
+                    "type": event,
+                    "record": { "id": 1 },
+                },
+            }),
+            msg_ref: None,
+            join_ref: None,
+        };
+        let txt = serde_json::to_string(&msg).unwrap();
+        ws.send(WsMessage::Text(txt.into())).await.unwrap();
+    }
+
+    async fn next_event(changes: &mut PostgresChanges) -> PostgresChangeEvent {
+        changes.next().await.unwrap().event_type
+    }
+
+    fn presence(refs: &[u64]) -> Value {
+        let metas: Vec<Value> = refs
+            .iter()
+            .map(|r| json!({ "phx_ref": r.to_string() }))
+            .collect();
+        json!({ "metas": metas })
+    }
+
+    fn refs(metas: &[Value]) -> String {
+        let refs: Vec<&str> = metas.iter().filter_map(|m| m["phx_ref"].as_str()).collect();
+        format!("[{}]", refs.join(","))
+    }
+
+    fn describe(event: PresenceEvent) -> String {
+        match event {
+            PresenceEvent::Sync(_) => "sync".to_string(),
+            PresenceEvent::Join {
+                key,
+                current,
+                joined,
+            } => {
+                format!("join {} {} +{}", key, refs(&current), refs(&joined))
+            }
+            PresenceEvent::Leave { key, current, left } => {
+                format!("leave {} {} -{}", key, refs(&current), refs(&left))
+            }
+        }
+    }
+
+    #[test]
+    fn applies_presence_state_and_diffs() {
+        let state = |key: &str, r: &[u64]| ("presence_state", json!({ key: presence(r) }));
+        let joins =
+            |key: &str, r: &[u64]| ("presence_diff", json!({ "joins": { key: presence(r) } }));
+        let leaves =
+            |key: &str, r: &[u64]| ("presence_diff", json!({ "leaves": { key: presence(r) } }));
+
+        // steps, resulting state, emitted events
+        let cases = [
+            (
+                vec![state("a", &[1]), joins("b", &[2])],
+                "a=[1] b=[2]",
+                vec!["sync", "join b [] +[2]", "sync"],
+            ),
+            (
+                vec![state("a", &[1]), joins("a", &[2])],
+                "a=[1,2]",
+                vec!["sync", "join a [1] +[2]", "sync"],
+            ),
+            (
+                vec![state("a", &[1]), joins("a", &[1])],
+                "a=[1]",
+                vec!["sync", "join a [1] +[1]", "sync"],
+            ),
+            (
+                vec![state("a", &[1, 2]), leaves("a", &[1])],
+                "a=[2]",
+                vec!["sync", "leave a [2] -[1]", "sync"],
+            ),
+            (
+                vec![state("a", &[1]), leaves("a", &[1])],
+                "",
+                vec!["sync", "leave a [] -[1]", "sync"],
+            ),
+            (
+                vec![state("a", &[1]), leaves("b", &[2])],
+                "a=[1]",
+                vec!["sync", "sync"],
+            ),
+            (
+                vec![
+                    state("a", &[1]),
+                    (
+                        "presence_diff",
+                        json!({ "joins": { "a": presence(&[2]) }, "leaves": { "a": presence(&[1]) } }),
+                    ),
+                ],
+                "a=[2]",
+                vec!["sync", "join a [1] +[2]", "leave a [2] -[1]", "sync"],
+            ),
+            (
+                vec![state("a", &[1]), state("b", &[2])],
+                "b=[2]",
+                vec!["sync", "sync"],
+            ),
+        ];
+
+        for (steps, expected_state, expected_events) in cases {
+            let realtime = SupabaseClient::new().realtime();
+            let channel = realtime.channel("room");
+            let mut events = channel.on_presence();
+
+            for (event, payload) in &steps {
+                realtime.shared.handle(Message {
+                    topic: channel.topic.clone(),
+                    event: event.to_string(),
+                    payload: payload.clone(),
+                    msg_ref: None,
+                    join_ref: None,
+                });
+            }
+
+            let mut state: Vec<String> = channel
+                .presence_state()
+                .iter()
+                .map(|(key, metas)| format!("{}={}", key, refs(metas)))
+                .collect();
+            state.sort();
+            assert_eq!(state.join(" "), expected_state, "{:?}", steps);
+
+            let mut emitted = vec![];
+            while let Ok(event) = events.rx.try_recv() {
+                emitted.push(describe(event));
+            }
+            assert_eq!(emitted, expected_events, "{:?}", steps);
+        }
+    }
+
+    #[tokio::test]
+    async fn routes_changes_by_server_ids() {
+        let (realtime, mut sockets) = ws_server().await;
+        let channel = realtime.channel("todos");
+        let filter = PostgresChangesFilter::new("public").table("todos");
+        let mut inserts =
+            channel.on_postgres_changes(filter.clone().event(PostgresChangeEvent::Insert));
+        let mut deletes = channel.on_postgres_changes(filter.event(PostgresChangeEvent::Delete));
+
+        let (result, (mut ws, join)) = tokio::join!(channel.subscribe(), async {
+            let mut ws = sockets.recv().await.unwrap();
+            let join = recv(&mut ws).await;
+            let ids = json!({ "postgres_changes": [{ "id": 11 }, { "id": 22 }] });
+            reply(&mut ws, &join, "ok", ids).await;
+            (ws, join)
+        });
+        result.unwrap();
+
+        assert_eq!(join.event, "phx_join");
+        assert_eq!(join.topic, "realtime:todos");
+        assert_eq!(join.payload["access_token"], "anon");
+        assert_eq!(
+            join.payload["config"]["postgres_changes"][1]["event"],
+            "DELETE"
+        );
+        assert_eq!(channel.status(), ChannelStatus::Joined);
+
+        // a change only reaches the binding whose server id it carries
+        change(&mut ws, &join.topic, &[22], "DELETE").await;
+        change(&mut ws, &join.topic, &[11], "INSERT").await;
+        change(&mut ws, &join.topic, &[33], "UPDATE").await;
+        change(&mut ws, &join.topic, &[11, 22], "INSERT").await;
+
+        assert_eq!(next_event(&mut deletes).await, PostgresChangeEvent::Delete);
+        assert_eq!(next_event(&mut deletes).await, PostgresChangeEvent::Insert);
+        assert_eq!(next_event(&mut inserts).await, PostgresChangeEvent::Insert);
+        assert_eq!(next_event(&mut inserts).await, PostgresChangeEvent::Insert);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), inserts.next())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn rejected_join_is_an_error() {
+        let (realtime, mut sockets) = ws_server().await;
+        let channel = realtime.channel("private");
+
+        let (result, _ws) = tokio::join!(channel.subscribe(), async {
+            let mut ws = sockets.recv().await.unwrap();
+            let join = recv(&mut ws).await;
+            reply(&mut ws, &join, "error", json!({ "reason": "Unauthorized" })).await;
+            ws
+        });
+
+        match result {
+            Err(Error::Realtime { message }) => assert_eq!(message, "Unauthorized"),
+            other => panic!("expected a realtime error, got {:?}", other),
+        }
+        assert_eq!(
+            channel.status(),
+            ChannelStatus::Errored("Unauthorized".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn unanswered_join_times_out() {
+        let (realtime, mut sockets) = ws_server().await;
+        let channel = realtime
+            .timeout(Duration::from_millis(100))
+            .channel("todos");
+
+        let (result, _ws) = tokio::join!(channel.subscribe(), async {
+            let mut ws = sockets.recv().await.unwrap();
+            recv(&mut ws).await;
+            ws
+        });
+
+        match result {
+            Err(Error::Realtime { message }) => assert!(message.contains("timed out")),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn rejoins_after_the_socket_drops() {
+        let (realtime, mut sockets) = ws_server().await;
+        let channel = realtime.channel("todos");
+        let filter = PostgresChangesFilter::new("public").table("todos");
+        let mut changes = channel.on_postgres_changes(filter);
+
+        let (result, (mut ws, first)) = tokio::join!(channel.subscribe(), async {
+            let mut ws = sockets.recv().await.unwrap();
+            let join = recv(&mut ws).await;
+            reply(
+                &mut ws,
+                &join,
+                "ok",
+                json!({ "postgres_changes": [{ "id": 1 }] }),
+            )
+            .await;
+            (ws, join)
+        });
+        result.unwrap();
+
+        ws.close(None).await.unwrap();
+        drop(ws);
+
+        // the socket task reconnects after its first backoff step and replays
+        // the join with a fresh ref and a new server id
+        let mut ws = tokio::time::timeout(Duration::from_secs(5), sockets.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let second = recv(&mut ws).await;
+        assert_eq!(second.event, "phx_join");
+        assert_eq!(second.topic, first.topic);
+        assert_ne!(second.join_ref, first.join_ref);
+        assert_eq!(channel.status(), ChannelStatus::Joining);
+
+        reply(
+            &mut ws,
+            &second,
+            "ok",
+            json!({ "postgres_changes": [{ "id": 2 }] }),
+        )
+        .await;
+        change(&mut ws, &second.topic, &[1], "UPDATE").await;
+        change(&mut ws, &second.topic, &[2], "INSERT").await;
+
+        assert_eq!(next_event(&mut changes).await, PostgresChangeEvent::Insert);
+        assert_eq!(channel.status(), ChannelStatus::Joined);
+    }
+}
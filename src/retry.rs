@@ -0,0 +1,309 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+use reqwest::header::RETRY_AFTER;
+
+use crate::utils::unix_now;
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    // total number of tries, including the first one
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    // fraction of each delay, between 0 and 1, that is randomized away
+    pub jitter: f64,
+    pub retry_statuses: Vec<u16>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(5),
+            jitter: 0.5,
+            retry_statuses: vec![502, 503, 504],
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    pub fn max_attempts(self, max_attempts: u32) -> Self {
+        Self {
+            max_attempts,
+            ..self
+        }
+    }
+
+    pub fn base_delay(self, base_delay: Duration) -> Self {
+        Self { base_delay, ..self }
+    }
+
+    pub fn max_delay(self, max_delay: Duration) -> Self {
+        Self { max_delay, ..self }
+    }
+
+    pub fn jitter(self, jitter: f64) -> Self {
+        Self {
+            jitter: jitter.clamp(0.0, 1.0),
+            ..self
+        }
+    }
+
+    pub fn retry_statuses(self, retry_statuses: &[u16]) -> Self {
+        Self {
+            retry_statuses: retry_statuses.to_vec(),
+            ..self
+        }
+    }
+
+    pub(crate) fn should_retry_status(&self, status: u16) -> bool {
+        self.retry_statuses.contains(&status)
+    }
+
+    pub(crate) fn should_retry_error(&self, e: &reqwest::Error) -> bool {
+        e.is_connect() || e.is_timeout() || e.is_request()
+    }
+
+    // exponential backoff for the given attempt, starting at 1
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        let exp = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+
+        // the fields are public, so the builder's clamp may have been skipped
+        let jitter = if self.jitter.is_nan() {
+            0.0
+        } else {
+            self.jitter.clamp(0.0, 1.0)
+        };
+        exp.mul_f64(1.0 - jitter * random_unit())
+    }
+
+    // the server's Retry-After wins over our own backoff, but never waits
+    // longer than `max_delay`
+    pub(crate) fn delay_for(&self, attempt: u32, res: &reqwest::Response) -> Duration {
+        res.headers()
+            .get(RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(retry_after)
+            .map(|delay| delay.min(self.max_delay))
+            .unwrap_or_else(|| self.delay(attempt))
+    }
+}
+
+// Retry-After is either seconds or an http date like
+// `Wed, 21 Oct 2015 07:28:00 GMT`
+fn retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let secs = http_date(value)? - unix_now();
+    Some(Duration::from_secs(secs.max(0) as u64))
+}
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+// seconds since the epoch of an IMF-fixdate, the form servers must send
+fn http_date(value: &str) -> Option<i64> {
+    let mut parts = value.split_whitespace();
+    let _weekday = parts.next()?;
+    let day = parts.next()?.parse::<i64>().ok()?;
+    let month = parts.next()?;
+    let month = MONTHS.iter().position(|m| *m == month)? as i64 + 1;
+    let year = parts.next()?.parse::<i64>().ok()?;
+    let mut time = parts.next()?.split(':').map(|t| t.parse::<i64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if parts.next()? != "GMT" {
+        return None;
+    }
+
+    // days from civil, shifting the year to start in march
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    Some(days * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+// a random number in [0, 1) without pulling in a rng crate
+fn random_unit() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(0);
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::time::Instant;
+
+    use super::*;
+    use crate::client::{Body, Method, RequestOptions};
+    use crate::test_server::{Reply, Server};
+
+    // answers with the scripted replies in order, then with 200
+    async fn scripted(replies: Vec<Reply>) -> Server {
+        let replies = Mutex::new(replies.into_iter());
+        Server::start(move |_| {
+            replies
+                .lock()
+                .unwrap()
+                .next()
+                .unwrap_or_else(|| Reply::status(200))
+        })
+        .await
+    }
+
+    fn options(idempotent: bool) -> RequestOptions {
+        RequestOptions {
+            idempotent,
+            ..RequestOptions::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn get_retries_503_and_honours_retry_after() {
+        let server = scripted(vec![Reply::status(503).header("Retry-After", "1")]).await;
+        let client = server.client();
+
+        let started = Instant::now();
+        let res = client
+            .send(
+                Method::GET,
+                "rest/v1/todos",
+                Body::Empty,
+                None,
+                &options(false),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(res.status(), 200);
+        assert_eq!(server.requests().len(), 2);
+        assert!(started.elapsed() >= Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn retry_after_is_capped_by_max_delay() {
+        let server = scripted(vec![Reply::status(503).header("Retry-After", "3600")]).await;
+        let client = server
+            .client()
+            .retry_policy(RetryPolicy::default().max_delay(Duration::from_millis(50)));
+
+        let started = Instant::now();
+        let res = client
+            .send(
+                Method::GET,
+                "rest/v1/todos",
+                Body::Empty,
+                None,
+                &options(false),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(res.status(), 200);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn post_is_only_retried_when_idempotent() {
+        let server = scripted(vec![Reply::status(503)]).await;
+        let client = server.client();
+        let res = client
+            .send(
+                Method::POST,
+                "rest/v1/todos",
+                Body::Json(serde_json::json!({})),
+                None,
+                &options(false),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 503);
+        assert_eq!(server.requests().len(), 1);
+
+        let server = scripted(vec![Reply::status(503)]).await;
+        let client = server.client();
+        let res = client
+            .send(
+                Method::POST,
+                "rest/v1/todos",
+                Body::Json(serde_json::json!({})),
+                None,
+                &options(true),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn multipart_is_never_resent() {
+        let server = scripted(vec![Reply::status(503)]).await;
+        let client = server.client();
+        let form = reqwest::multipart::Form::new().text("file", "contents");
+
+        let res = client
+            .send(
+                Method::POST,
+                "storage/v1/object/b/f",
+                Body::Form(form),
+                None,
+                &options(true),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(res.status(), 503);
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn delay_clamps_jitter_set_directly() {
+        let policy = RetryPolicy {
+            jitter: 2.0,
+            ..RetryPolicy::default()
+        };
+        assert!(policy.delay(1) <= policy.base_delay);
+
+        let policy = RetryPolicy {
+            jitter: -1.0,
+            ..RetryPolicy::default()
+        };
+        assert_eq!(policy.delay(1), policy.base_delay);
+    }
+
+    #[test]
+    fn parses_retry_after() {
+        assert_eq!(retry_after(" 120 "), Some(Duration::from_secs(120)));
+        assert_eq!(http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(784111777));
+        assert_eq!(
+            retry_after("Sun, 06 Nov 1994 08:49:37 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(retry_after("soon"), None);
+    }
+}
@@ -0,0 +1,226 @@
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Join {
+    Inner,
+    Left,
+}
+
+// one entry of a select list, rendered in postgrest's select syntax
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Field {
+    All,
+    Column {
+        name: String,
+        alias: Option<String>,
+        cast: Option<String>,
+    },
+    Embed {
+        table: String,
+        alias: Option<String>,
+        // foreign key or column disambiguating the relationship
+        hint: Option<String>,
+        join: Option<Join>,
+        // lifts the embedded columns into the parent row
+        spread: bool,
+        fields: Vec<Field>,
+    },
+}
+
+impl Field {
+    pub fn all() -> Field {
+        Field::All
+    }
+
+    pub fn column(name: &str) -> Field {
+        Field::Column {
+            name: name.to_string(),
+            alias: None,
+            cast: None,
+        }
+    }
+
+    // a json path, `data->foo->>bar` for `json("data", &["foo", "bar"], true)`
+    pub fn json(column: &str, path: &[&str], as_text: bool) -> Field {
+        let mut name = column.to_string();
+        for (i, key) in path.iter().enumerate() {
+            let arrow = if as_text && i == path.len() - 1 {
+                "->>"
+            } else {
+                "->"
+            };
+            name.push_str(arrow);
+            name.push_str(key);
+        }
+        Field::column(&name)
+    }
+
+    pub fn embed<I>(table: &str, fields: I) -> Field
+    where
+        I: IntoIterator<Item = Field>,
+    {
+        Field::Embed {
+            table: table.to_string(),
+            alias: None,
+            hint: None,
+            join: None,
+            spread: false,
+            fields: fields.into_iter().collect(),
+        }
+    }
+
+    pub fn alias(mut self, alias: &str) -> Field {
+        match &mut self {
+            Field::Column { alias: a, .. } | Field::Embed { alias: a, .. } => {
+                *a = Some(alias.to_string())
+            }
+            Field::All => {}
+        }
+        self
+    }
+
+    pub fn cast(mut self, cast: &str) -> Field {
+        if let Field::Column { cast: c, .. } = &mut self {
+            *c = Some(cast.to_string());
+        }
+        self
+    }
+
+    pub fn hint(mut self, hint: &str) -> Field {
+        if let Field::Embed { hint: h, .. } = &mut self {
+            *h = Some(hint.to_string());
+        }
+        self
+    }
+
+    // only keeps parent rows that have a matching embedded row
+    pub fn inner(self) -> Field {
+        self.join(Join::Inner)
+    }
+
+    pub fn left(self) -> Field {
+        self.join(Join::Left)
+    }
+
+    pub fn spread(mut self) -> Field {
+        if let Field::Embed { spread, .. } = &mut self {
+            *spread = true;
+        }
+        self
+    }
+
+    fn join(mut self, join: Join) -> Field {
+        if let Field::Embed { join: j, .. } = &mut self {
+            *j = Some(join);
+        }
+        self
+    }
+
+    fn render(&self) -> String {
+        match self {
+            Field::All => "*".to_string(),
+            Field::Column { name, alias, cast } => {
+                let mut out = String::new();
+                if let Some(alias) = alias {
+                    out.push_str(&format!("{}:", alias));
+                }
+                out.push_str(name);
+                if let Some(cast) = cast {
+                    out.push_str(&format!("::{}", cast));
+                }
+                out
+            }
+            Field::Embed {
+                table,
+                alias,
+                hint,
+                join,
+                spread,
+                fields,
+            } => {
+                let mut out = String::new();
+                if *spread {
+                    out.push_str("...");
+                }
+                if let Some(alias) = alias {
+                    out.push_str(&format!("{}:", alias));
+                }
+                out.push_str(table);
+                if let Some(hint) = hint {
+                    out.push_str(&format!("!{}", hint));
+                }
+                match join {
+                    Some(Join::Inner) => out.push_str("!inner"),
+                    Some(Join::Left) => out.push_str("!left"),
+                    None => {}
+                }
+                out.push_str(&format!("({})", render(fields)));
+                out
+            }
+        }
+    }
+}
+
+pub(crate) fn render(fields: &[Field]) -> String {
+    fields
+        .iter()
+        .map(Field::render)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_select_lists() {
+        let cases = [
+            (vec![Field::all()], "*"),
+            (vec![Field::column("id"), Field::column("name")], "id,name"),
+            (
+                vec![Field::column("name").alias("title").cast("text")],
+                "title:name::text",
+            ),
+            (vec![Field::json("data", &["a", "b"], true)], "data->a->>b"),
+            (vec![Field::json("data", &["a", "b"], false)], "data->a->b"),
+            (
+                vec![
+                    Field::column("id"),
+                    Field::embed("users", [Field::column("name")]).alias("author"),
+                ],
+                "id,author:users(name)",
+            ),
+            (
+                vec![
+                    Field::embed("users", [Field::all()])
+                        .hint("owner_id")
+                        .inner(),
+                ],
+                "users!owner_id!inner(*)",
+            ),
+            (
+                vec![Field::embed("teams", [Field::column("name")]).left()],
+                "teams!left(name)",
+            ),
+            (
+                vec![Field::embed("profiles", [Field::column("bio")]).spread()],
+                "...profiles(bio)",
+            ),
+            (
+                vec![Field::embed(
+                    "posts",
+                    [
+                        Field::column("title"),
+                        Field::embed("comments", [Field::column("body")]).inner(),
+                    ],
+                )],
+                "posts(title,comments!inner(body))",
+            ),
+            // column builders do nothing on the wrong kind of field
+            (vec![Field::all().alias("x").cast("text").hint("y")], "*"),
+        ];
+
+        for (fields, expected) in cases {
+            assert_eq!(render(&fields), expected, "{:?}", fields);
+        }
+    }
+}
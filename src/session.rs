@@ -0,0 +1,315 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, broadcast};
+
+use crate::client::UserResponse;
+
+pub const DEFAULT_STORAGE_KEY: &str = "supabase.auth.token";
+
+pub type StorageError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Session {
+    pub access_token: Option<String>,
+    pub refresh_token: Option<String>,
+    pub expires_at: Option<i64>,
+    pub user: Option<UserResponse>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthEvent {
+    SignedIn,
+    SignedOut,
+    TokenRefreshed,
+    MfaChallengeVerified,
+}
+
+#[derive(Debug, Clone)]
+pub struct AuthChange {
+    pub event: AuthEvent,
+    pub session: Session,
+}
+
+pub trait SessionStorage: Send + Sync {
+    fn get_item(&self, key: &str) -> Result<Option<String>, StorageError>;
+    fn set_item(&self, key: &str, value: &str) -> Result<(), StorageError>;
+    fn remove_item(&self, key: &str) -> Result<(), StorageError>;
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    items: std::sync::Mutex<HashMap<String, String>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SessionStorage for MemoryStorage {
+    fn get_item(&self, key: &str) -> Result<Option<String>, StorageError> {
+        Ok(self.items.lock().unwrap().get(key).cloned())
+    }
+
+    fn set_item(&self, key: &str, value: &str) -> Result<(), StorageError> {
+        self.items
+            .lock()
+            .unwrap()
+            .insert(key.to_owned(), value.to_owned());
+        Ok(())
+    }
+
+    fn remove_item(&self, key: &str) -> Result<(), StorageError> {
+        self.items.lock().unwrap().remove(key);
+        Ok(())
+    }
+}
+
+// keeps every key in a single json object on disk
+#[derive(Debug)]
+pub struct FileStorage {
+    path: PathBuf,
+    lock: std::sync::Mutex<()>,
+}
+
+impl FileStorage {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: std::sync::Mutex::new(()),
+        }
+    }
+
+    fn read_items(&self) -> Result<HashMap<String, String>, StorageError> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(txt) if txt.trim().is_empty() => Ok(HashMap::new()),
+            Ok(txt) => Ok(serde_json::from_str(&txt)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HashMap::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn write_items(&self, items: &HashMap<String, String>) -> Result<(), StorageError> {
+        if let Some(dir) = self.path.parent()
+            && !dir.as_os_str().is_empty()
+        {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        // written next to the target and renamed over it, so a reader or a
+        // concurrent run never sees a half written file
+        let tmp = self.path.with_file_name(format!(
+            ".{}.{}.{}.tmp",
+            self.path
+                .file_name()
+                .map(|n| n.to_string_lossy())
+                .unwrap_or_default(),
+            std::process::id(),
+            TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let written = write_private(&tmp, serde_json::to_string_pretty(items)?.as_bytes())
+            .and_then(|_| std::fs::rename(&tmp, &self.path));
+        if written.is_err() {
+            let _ = std::fs::remove_file(&tmp);
+        }
+        Ok(written?)
+    }
+}
+
+static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+// the file holds refresh tokens, so only the owner may read it
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let mut file = options.open(path)?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+impl SessionStorage for FileStorage {
+    fn get_item(&self, key: &str) -> Result<Option<String>, StorageError> {
+        let _guard = self.lock.lock().unwrap();
+        Ok(self.read_items()?.remove(key))
+    }
+
+    fn set_item(&self, key: &str, value: &str) -> Result<(), StorageError> {
+        let _guard = self.lock.lock().unwrap();
+        let mut items = self.read_items()?;
+        items.insert(key.to_owned(), value.to_owned());
+        self.write_items(&items)
+    }
+
+    fn remove_item(&self, key: &str) -> Result<(), StorageError> {
+        let _guard = self.lock.lock().unwrap();
+        let mut items = self.read_items()?;
+        if items.remove(key).is_some() {
+            self.write_items(&items)?;
+        }
+        Ok(())
+    }
+}
+
+// one store is shared by the client and every clone of it, including the
+// ones held by TableSchema and BucketSchema, so they all read the latest
+// tokens on each request
+#[derive(Clone)]
+pub(crate) struct SessionStore {
+    session: Arc<RwLock<Session>>,
+    events: broadcast::Sender<AuthChange>,
+    pub(crate) refresh_lock: Arc<Mutex<()>>,
+    pub(crate) storage: Arc<dyn SessionStorage>,
+    pub(crate) storage_key: String,
+}
+
+impl fmt::Debug for SessionStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionStore")
+            .field("session", &self.session)
+            .field("storage_key", &self.storage_key)
+            .finish()
+    }
+}
+
+impl SessionStore {
+    pub(crate) fn new() -> Self {
+        Self::load(Arc::new(MemoryStorage::new()), DEFAULT_STORAGE_KEY)
+    }
+
+    // restores whatever session was last persisted under `key`
+    pub(crate) fn load(storage: Arc<dyn SessionStorage>, key: &str) -> Self {
+        let (events, _) = broadcast::channel(16);
+
+        let session = storage
+            .get_item(key)
+            .ok()
+            .flatten()
+            .and_then(|txt| serde_json::from_str(&txt).ok())
+            .unwrap_or_default();
+
+        Self {
+            session: Arc::new(RwLock::new(session)),
+            events,
+            refresh_lock: Arc::new(Mutex::new(())),
+            storage,
+            storage_key: key.to_owned(),
+        }
+    }
+
+    pub(crate) fn get(&self) -> Session {
+        self.session.read().unwrap().clone()
+    }
+
+    pub(crate) fn access_token(&self) -> Option<String> {
+        self.session.read().unwrap().access_token.clone()
+    }
+
+    pub(crate) fn refresh_token(&self) -> Option<String> {
+        self.session.read().unwrap().refresh_token.clone()
+    }
+
+    pub(crate) fn expires_at(&self) -> Option<i64> {
+        self.session.read().unwrap().expires_at
+    }
+
+    pub(crate) fn set(&self, session: Session, event: AuthEvent) {
+        *self.session.write().unwrap() = session.clone();
+        self.persist(&session);
+        self.notify(event, session);
+    }
+
+    pub(crate) fn set_user(&self, user: UserResponse) {
+        let session = {
+            let mut session = self.session.write().unwrap();
+            session.user = Some(user);
+            session.clone()
+        };
+        self.persist(&session);
+    }
+
+    pub(crate) fn clear(&self) {
+        self.set(Session::default(), AuthEvent::SignedOut);
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<AuthChange> {
+        self.events.subscribe()
+    }
+
+    // the PKCE verifier has to survive until the provider redirects back,
+    // possibly into a restarted app
+    pub(crate) fn set_code_verifier(&self, verifier: &str) -> Result<(), StorageError> {
+        self.storage.set_item(&self.code_verifier_key(), verifier)
+    }
+
+    pub(crate) fn take_code_verifier(&self) -> Option<String> {
+        let key = self.code_verifier_key();
+        let verifier = self.storage.get_item(&key).ok().flatten();
+        let _ = self.storage.remove_item(&key);
+        verifier
+    }
+
+    fn code_verifier_key(&self) -> String {
+        format!("{}-code-verifier", self.storage_key)
+    }
+
+    // persisting is best effort, a failing storage must not break auth
+    fn persist(&self, session: &Session) {
+        let _ = match session.access_token {
+            Some(_) => serde_json::to_string(session)
+                .map_err(StorageError::from)
+                .and_then(|txt| self.storage.set_item(&self.storage_key, &txt)),
+            None => self.storage.remove_item(&self.storage_key),
+        };
+    }
+
+    fn notify(&self, event: AuthEvent, session: Session) {
+        // no subscribers is not an error
+        let _ = self.events.send(AuthChange { event, session });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_storage_writes_private_files_atomically() {
+        let dir = std::env::temp_dir().join(format!("supa_rs_session_{}", std::process::id()));
+        let path = dir.join("session.json");
+        let storage = FileStorage::new(&path);
+
+        storage.set_item("a", "1").unwrap();
+        storage.set_item("b", "2").unwrap();
+        storage.remove_item("a").unwrap();
+        assert_eq!(storage.get_item("a").unwrap(), None);
+        assert_eq!(storage.get_item("b").unwrap().as_deref(), Some("2"));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // no temp files are left behind
+        let entries = std::fs::read_dir(&dir).unwrap().count();
+        assert_eq!(entries, 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
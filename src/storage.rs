@@ -76,7 +76,7 @@ impl SupabaseClient {
     }
 
     pub async fn create_bucket(&self, name: &str, public: bool) -> Result<Response<String>, Error> {
-        self.request(
+        self.authed_request(
             Method::POST,
             "storage/v1/bucket",
             json!({
@@ -84,86 +84,58 @@ impl SupabaseClient {
                 "name": name,
                 "public": public
             }),
-            Some(&self.access_token.clone().unwrap_or("".to_owned())),
             None,
         )
         .await
     }
 
     pub async fn list_buckets(&self) -> Result<Response<Vec<BucketResponse>>, Error> {
-        let res = self
-            .request(
-                Method::GET,
-                "storage/v1/bucket",
-                json!({}),
-                Some(&self.access_token.clone().unwrap_or("".to_owned())),
-                None,
-            )
-            .await?;
-
-        Ok(Response {
-            code: res.code,
-            data: serde_json::from_str(&res.data.unwrap()).unwrap(),
-        })
+        self.authed_request(Method::GET, "storage/v1/bucket", json!({}), None)
+            .await?
+            .json()
     }
 }
 
 impl BucketSchema {
     pub async fn get_bucket(&self) -> Result<Response<BucketResponse>, Error> {
-        let res = self
-            .client
-            .request(
+        self.client
+            .authed_request(
                 Method::GET,
                 &format!("storage/v1/bucket/{}", self.name),
                 json!({}),
-                Some(&self.client.access_token.clone().unwrap_or("".to_owned())),
                 None,
             )
-            .await?;
-
-        Ok(Response {
-            code: res.code,
-            data: serde_json::from_str(&res.data.unwrap()).unwrap(),
-        })
+            .await?
+            .json()
     }
 
     pub async fn update_bucket(
         &self,
         data: UpdateRequest,
     ) -> Result<Response<MessageResponse>, Error> {
-        let res = self
-            .client
-            .request(
+        self.client
+            .authed_request(
                 Method::PUT,
                 &format!("storage/v1/bucket/{}", self.name),
-                serde_json::to_value(data).unwrap(),
-                Some(&self.client.access_token.clone().unwrap_or("".to_owned())),
+                serde_json::to_value(data).map_err(|e| Error::InvalidArgument {
+                    message: e.to_string(),
+                })?,
                 None,
             )
-            .await?;
-
-        Ok(Response {
-            code: res.code,
-            data: serde_json::from_str(&res.data.unwrap()).unwrap(),
-        })
+            .await?
+            .json()
     }
 
     pub async fn delete_bucket(&self) -> Result<Response<MessageResponse>, Error> {
-        let res = self
-            .client
-            .request(
+        self.client
+            .authed_request(
                 Method::DELETE,
                 &format!("storage/v1/bucket/{}", self.name),
                 json!({}),
-                Some(&self.client.access_token.clone().unwrap_or("".to_owned())),
                 None,
             )
-            .await?;
-
-        Ok(Response {
-            code: res.code,
-            data: serde_json::from_str(&res.data.unwrap()).unwrap(),
-        })
+            .await?
+            .json()
     }
 
     pub async fn upload(
@@ -176,37 +148,27 @@ impl BucketSchema {
             "file",
             reqwest::multipart::Part::bytes(file_data)
                 .file_name("filename")
-                .mime_str("application/octet-stream")
-                .unwrap(),
+                .mime_str("application/octet-stream")?,
         );
 
         // TODO: upsert is not working
-        let res = self
-            .client
-            .request(
+        self.client
+            .authed_request(
                 Method::POST,
                 &format!(
                     "storage/v1/object/{}/{}?upsert={}",
                     self.name, file_path, upsert
                 ),
                 json!({}),
-                Some(&self.client.access_token.clone().unwrap_or("".to_owned())),
                 Some(form),
             )
-            .await?;
-
-        Ok(Response {
-            code: res.code,
-            data: serde_json::from_str(&res.data.unwrap()).unwrap(),
-        })
+            .await?
+            .json()
     }
 
     pub async fn get(&self, file_path: &str) -> Result<Response<Vec<u8>>, Error> {
         self.client
-            .request_bytes(
-                &format!("storage/v1/object/{}/{}", self.name, file_path),
-                Some(&self.client.access_token.clone().unwrap_or("".to_owned())),
-            )
+            .authed_request_bytes(&format!("storage/v1/object/{}/{}", self.name, file_path))
             .await
     }
 
@@ -215,9 +177,8 @@ impl BucketSchema {
         limit: i32,
         offset: i32,
     ) -> Result<Response<Vec<FilesResponse>>, Error> {
-        let res = self
-            .client
-            .request(
+        self.client
+            .authed_request(
                 Method::POST,
                 &format!("storage/v1/object/list/{}", self.name),
                 json!({
@@ -229,15 +190,10 @@ impl BucketSchema {
                             "order": "asc",
                         },
                 }),
-                Some(&self.client.access_token.clone().unwrap_or("".to_owned())),
                 None,
             )
-            .await?;
-
-        Ok(Response {
-            code: res.code,
-            data: serde_json::from_str(&res.data.unwrap()).unwrap(),
-        })
+            .await?
+            .json()
     }
 
     pub async fn search(
@@ -245,9 +201,8 @@ impl BucketSchema {
         term: &str,
         limit: i32,
     ) -> Result<Response<Vec<FilesResponse>>, Error> {
-        let res = self
-            .client
-            .request(
+        self.client
+            .authed_request(
                 Method::POST,
                 &format!("storage/v1/object/list/{}", self.name),
                 json!({
@@ -255,33 +210,22 @@ impl BucketSchema {
                     "limit": limit,
                     "prefix": ""
                 }),
-                Some(&self.client.access_token.clone().unwrap_or("".to_owned())),
                 None,
             )
-            .await?;
-
-        Ok(Response {
-            code: res.code,
-            data: serde_json::from_str(&res.data.unwrap()).unwrap(),
-        })
+            .await?
+            .json()
     }
 
     pub async fn delete(&self, file_path: &str) -> Result<Response<MessageResponse>, Error> {
-        let res = self
-            .client
-            .request(
+        self.client
+            .authed_request(
                 Method::DELETE,
                 &format!("storage/v1/object/{}/{}", self.name, file_path),
                 json!({}),
-                Some(&self.client.access_token.clone().unwrap_or("".to_owned())),
                 None,
             )
-            .await?;
-
-        Ok(Response {
-            code: res.code,
-            data: serde_json::from_str(&res.data.unwrap()).unwrap(),
-        })
+            .await?
+            .json()
     }
 
     pub async fn get_public_url(&self, file_path: &str) -> Result<Response<String>, Error> {
@@ -292,6 +236,7 @@ impl BucketSchema {
                 self.client.base_url, self.name, file_path
             )
             .into(),
+            count: None,
         })
     }
 
@@ -302,25 +247,23 @@ impl BucketSchema {
     ) -> Result<Response<String>, Error> {
         let res = self
             .client
-            .request(
+            .authed_request(
                 Method::POST,
                 &format!("storage/v1/object/sign/{}/{}", self.name, file_path),
                 json!({
                     "expiresIn": expires_in
                 }),
-                Some(&self.client.access_token.clone().unwrap_or("".to_owned())),
                 None,
             )
-            .await?;
-
-        let signed_url: SignedUrlResponse = serde_json::from_str(&res.data.unwrap()).unwrap();
+            .await?
+            .json::<SignedUrlResponse>()?;
 
         Ok(Response {
             code: res.code,
-            data: Some(format!(
-                "{}/storage/v1{}",
-                self.client.base_url, signed_url.url
-            )),
+            data: res
+                .data
+                .map(|signed_url| format!("{}/storage/v1{}", self.client.base_url, signed_url.url)),
+            count: None,
         })
     }
 }
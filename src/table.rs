@@ -0,0 +1,721 @@
+use std::marker::PhantomData;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::client::{Error, Response, SupabaseClient};
+use crate::filter::Filter;
+use crate::query::{CountMode, ExplainOptions, Returning, TableSchema};
+use crate::select::Field;
+
+// a row type bound to a table, usually implemented with
+// `#[derive(SupabaseTable)]` from the `derive` feature
+pub trait Table {
+    const NAME: &'static str;
+
+    // the row as it is inserted, with defaulted columns optional
+    type Insert: Serialize;
+    // a partial row, only the set columns are changed
+    type Update: Serialize;
+}
+
+// a column of table `T`, so filters only take columns that exist
+pub struct Column<T> {
+    name: &'static str,
+    table: PhantomData<fn() -> T>,
+}
+
+impl<T> Column<T> {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            table: PhantomData,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn eq(self, value: impl Into<Value>) -> TableFilter<T> {
+        TableFilter::new(Filter::eq(self.name, value))
+    }
+
+    pub fn neq(self, value: impl Into<Value>) -> TableFilter<T> {
+        TableFilter::new(Filter::neq(self.name, value))
+    }
+
+    pub fn gt(self, value: impl Into<Value>) -> TableFilter<T> {
+        TableFilter::new(Filter::gt(self.name, value))
+    }
+
+    pub fn gte(self, value: impl Into<Value>) -> TableFilter<T> {
+        TableFilter::new(Filter::gte(self.name, value))
+    }
+
+    pub fn lt(self, value: impl Into<Value>) -> TableFilter<T> {
+        TableFilter::new(Filter::lt(self.name, value))
+    }
+
+    pub fn lte(self, value: impl Into<Value>) -> TableFilter<T> {
+        TableFilter::new(Filter::lte(self.name, value))
+    }
+
+    pub fn like(self, pattern: &str) -> TableFilter<T> {
+        TableFilter::new(Filter::like(self.name, pattern))
+    }
+
+    pub fn ilike(self, pattern: &str) -> TableFilter<T> {
+        TableFilter::new(Filter::ilike(self.name, pattern))
+    }
+
+    pub fn is(self, value: impl Into<Value>) -> TableFilter<T> {
+        TableFilter::new(Filter::is(self.name, value))
+    }
+
+    pub fn in_a<I, V>(self, values: I) -> TableFilter<T>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<Value>,
+    {
+        TableFilter::new(Filter::in_a(self.name, values))
+    }
+
+    pub fn contains<I, V>(self, values: I) -> TableFilter<T>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<Value>,
+    {
+        TableFilter::new(Filter::contains(self.name, values))
+    }
+
+    pub fn contained_by<I, V>(self, values: I) -> TableFilter<T>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<Value>,
+    {
+        TableFilter::new(Filter::contained_by(self.name, values))
+    }
+
+    pub fn is_distinct(self, value: impl Into<Value>) -> TableFilter<T> {
+        TableFilter::new(Filter::is_distinct(self.name, value))
+    }
+
+    pub fn overlaps<I, V>(self, values: I) -> TableFilter<T>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<Value>,
+    {
+        TableFilter::new(Filter::overlaps(self.name, values))
+    }
+
+    pub fn range_lt(self, range: &str) -> TableFilter<T> {
+        TableFilter::new(Filter::range_lt(self.name, range))
+    }
+
+    pub fn range_gt(self, range: &str) -> TableFilter<T> {
+        TableFilter::new(Filter::range_gt(self.name, range))
+    }
+
+    pub fn range_lte(self, range: &str) -> TableFilter<T> {
+        TableFilter::new(Filter::range_lte(self.name, range))
+    }
+
+    pub fn range_gte(self, range: &str) -> TableFilter<T> {
+        TableFilter::new(Filter::range_gte(self.name, range))
+    }
+
+    pub fn range_adjacent(self, range: &str) -> TableFilter<T> {
+        TableFilter::new(Filter::range_adjacent(self.name, range))
+    }
+
+    pub fn range_overlaps(self, range: &str) -> TableFilter<T> {
+        TableFilter::new(Filter::range_overlaps(self.name, range))
+    }
+
+    pub fn like_all<I, S>(self, patterns: I) -> TableFilter<T>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        TableFilter::new(Filter::like_all(self.name, patterns))
+    }
+
+    pub fn like_any<I, S>(self, patterns: I) -> TableFilter<T>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        TableFilter::new(Filter::like_any(self.name, patterns))
+    }
+
+    pub fn ilike_all<I, S>(self, patterns: I) -> TableFilter<T>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        TableFilter::new(Filter::ilike_all(self.name, patterns))
+    }
+
+    pub fn ilike_any<I, S>(self, patterns: I) -> TableFilter<T>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        TableFilter::new(Filter::ilike_any(self.name, patterns))
+    }
+
+    pub fn regex_match(self, pattern: &str) -> TableFilter<T> {
+        TableFilter::new(Filter::regex_match(self.name, pattern))
+    }
+
+    pub fn regex_imatch(self, pattern: &str) -> TableFilter<T> {
+        TableFilter::new(Filter::regex_imatch(self.name, pattern))
+    }
+
+    pub fn fts(self, query: &str, config: Option<&str>) -> TableFilter<T> {
+        TableFilter::new(Filter::fts(self.name, query, config))
+    }
+
+    pub fn plfts(self, query: &str, config: Option<&str>) -> TableFilter<T> {
+        TableFilter::new(Filter::plfts(self.name, query, config))
+    }
+
+    pub fn phfts(self, query: &str, config: Option<&str>) -> TableFilter<T> {
+        TableFilter::new(Filter::phfts(self.name, query, config))
+    }
+
+    pub fn wfts(self, query: &str, config: Option<&str>) -> TableFilter<T> {
+        TableFilter::new(Filter::wfts(self.name, query, config))
+    }
+}
+
+impl<T> Clone for Column<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Column<T> {}
+
+impl<T> std::fmt::Debug for Column<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Column").field(&self.name).finish()
+    }
+}
+
+impl<T> AsRef<str> for Column<T> {
+    fn as_ref(&self) -> &str {
+        self.name
+    }
+}
+
+// a filter on the columns of table `T`, built from its `Column`s and combined
+// with `TableFilter::and`, `TableFilter::or` and `!`
+pub struct TableFilter<T> {
+    filter: Filter,
+    table: PhantomData<fn() -> T>,
+}
+
+impl<T> TableFilter<T> {
+    fn new(filter: Filter) -> Self {
+        Self {
+            filter,
+            table: PhantomData,
+        }
+    }
+
+    pub fn and(filters: impl IntoIterator<Item = TableFilter<T>>) -> Self {
+        Self::new(Filter::and(filters.into_iter().map(|f| f.filter)))
+    }
+
+    pub fn or(filters: impl IntoIterator<Item = TableFilter<T>>) -> Self {
+        Self::new(Filter::or(filters.into_iter().map(|f| f.filter)))
+    }
+
+    pub fn filter(&self) -> &Filter {
+        &self.filter
+    }
+}
+
+impl<T> Clone for TableFilter<T> {
+    fn clone(&self) -> Self {
+        Self::new(self.filter.clone())
+    }
+}
+
+impl<T> std::fmt::Debug for TableFilter<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("TableFilter").field(&self.filter).finish()
+    }
+}
+
+impl<T> std::ops::Not for TableFilter<T> {
+    type Output = TableFilter<T>;
+
+    fn not(self) -> TableFilter<T> {
+        Self::new(!self.filter)
+    }
+}
+
+// a query on table `T`, where filters only take `T`'s columns and writes take
+// its `Insert` and `Update` rows
+pub struct TypedTableSchema<T: Table> {
+    pub inner: TableSchema,
+    // a row that failed to serialize, reported when the query is sent
+    error: Option<String>,
+    table: PhantomData<fn() -> T>,
+}
+
+impl<T: Table> Clone for TypedTableSchema<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            error: self.error.clone(),
+            table: PhantomData,
+        }
+    }
+}
+
+impl<T: Table> std::fmt::Debug for TypedTableSchema<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TypedTableSchema")
+            .field("inner", &self.inner)
+            .field("error", &self.error)
+            .finish()
+    }
+}
+
+impl SupabaseClient {
+    // the typed entry point, prefer it over `from` for tables with a `Table`
+    // impl. the untyped filters take any `AsRef<str>`, so only this one
+    // rejects a `Column` of another table
+    pub fn from_table<T: Table>(&self) -> TypedTableSchema<T> {
+        TypedTableSchema {
+            inner: self.from(T::NAME),
+            error: None,
+            table: PhantomData,
+        }
+    }
+}
+
+impl<T: Table> TypedTableSchema<T> {
+    // overrides the client's schema for this query
+    pub fn schema(&mut self, schema: &str) -> TypedTableSchema<T> {
+        self.inner.schema(schema);
+        self.clone()
+    }
+
+    pub fn select(&mut self, columns: &str) -> TypedTableSchema<T> {
+        self.inner.select(columns);
+        self.clone()
+    }
+
+    pub fn select_columns(&mut self, columns: &[Column<T>]) -> TypedTableSchema<T> {
+        self.select_fields(columns.iter().map(|c| Field::column(c.name)))
+    }
+
+    pub fn select_fields<I>(&mut self, fields: I) -> TypedTableSchema<T>
+    where
+        I: IntoIterator<Item = Field>,
+    {
+        self.inner.select_fields(fields);
+        self.clone()
+    }
+
+    pub fn filter(&mut self, filter: TableFilter<T>) -> TypedTableSchema<T> {
+        self.inner.filter(filter.filter);
+        self.clone()
+    }
+
+    pub fn eq(&mut self, column: Column<T>, value: impl Into<Value>) -> TypedTableSchema<T> {
+        self.filter(column.eq(value))
+    }
+
+    pub fn neq(&mut self, column: Column<T>, value: impl Into<Value>) -> TypedTableSchema<T> {
+        self.filter(column.neq(value))
+    }
+
+    pub fn gt(&mut self, column: Column<T>, value: impl Into<Value>) -> TypedTableSchema<T> {
+        self.filter(column.gt(value))
+    }
+
+    pub fn gte(&mut self, column: Column<T>, value: impl Into<Value>) -> TypedTableSchema<T> {
+        self.filter(column.gte(value))
+    }
+
+    pub fn lt(&mut self, column: Column<T>, value: impl Into<Value>) -> TypedTableSchema<T> {
+        self.filter(column.lt(value))
+    }
+
+    pub fn lte(&mut self, column: Column<T>, value: impl Into<Value>) -> TypedTableSchema<T> {
+        self.filter(column.lte(value))
+    }
+
+    pub fn like(&mut self, column: Column<T>, pattern: &str) -> TypedTableSchema<T> {
+        self.filter(column.like(pattern))
+    }
+
+    pub fn ilike(&mut self, column: Column<T>, pattern: &str) -> TypedTableSchema<T> {
+        self.filter(column.ilike(pattern))
+    }
+
+    pub fn is(&mut self, column: Column<T>, value: impl Into<Value>) -> TypedTableSchema<T> {
+        self.filter(column.is(value))
+    }
+
+    pub fn in_a<I, V>(&mut self, column: Column<T>, values: I) -> TypedTableSchema<T>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<Value>,
+    {
+        self.filter(column.in_a(values))
+    }
+
+    pub fn contains<I, V>(&mut self, column: Column<T>, values: I) -> TypedTableSchema<T>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<Value>,
+    {
+        self.filter(column.contains(values))
+    }
+
+    pub fn contained_by<I, V>(&mut self, column: Column<T>, values: I) -> TypedTableSchema<T>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<Value>,
+    {
+        self.filter(column.contained_by(values))
+    }
+
+    pub fn is_distinct(
+        &mut self,
+        column: Column<T>,
+        value: impl Into<Value>,
+    ) -> TypedTableSchema<T> {
+        self.filter(column.is_distinct(value))
+    }
+
+    pub fn overlaps<I, V>(&mut self, column: Column<T>, values: I) -> TypedTableSchema<T>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<Value>,
+    {
+        self.filter(column.overlaps(values))
+    }
+
+    pub fn range_lt(&mut self, column: Column<T>, range: &str) -> TypedTableSchema<T> {
+        self.filter(column.range_lt(range))
+    }
+
+    pub fn range_gt(&mut self, column: Column<T>, range: &str) -> TypedTableSchema<T> {
+        self.filter(column.range_gt(range))
+    }
+
+    pub fn range_lte(&mut self, column: Column<T>, range: &str) -> TypedTableSchema<T> {
+        self.filter(column.range_lte(range))
+    }
+
+    pub fn range_gte(&mut self, column: Column<T>, range: &str) -> TypedTableSchema<T> {
+        self.filter(column.range_gte(range))
+    }
+
+    pub fn range_adjacent(&mut self, column: Column<T>, range: &str) -> TypedTableSchema<T> {
+        self.filter(column.range_adjacent(range))
+    }
+
+    pub fn range_overlaps(&mut self, column: Column<T>, range: &str) -> TypedTableSchema<T> {
+        self.filter(column.range_overlaps(range))
+    }
+
+    pub fn like_all<I, S>(&mut self, column: Column<T>, patterns: I) -> TypedTableSchema<T>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.filter(column.like_all(patterns))
+    }
+
+    pub fn like_any<I, S>(&mut self, column: Column<T>, patterns: I) -> TypedTableSchema<T>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.filter(column.like_any(patterns))
+    }
+
+    pub fn ilike_all<I, S>(&mut self, column: Column<T>, patterns: I) -> TypedTableSchema<T>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.filter(column.ilike_all(patterns))
+    }
+
+    pub fn ilike_any<I, S>(&mut self, column: Column<T>, patterns: I) -> TypedTableSchema<T>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.filter(column.ilike_any(patterns))
+    }
+
+    pub fn regex_match(&mut self, column: Column<T>, pattern: &str) -> TypedTableSchema<T> {
+        self.filter(column.regex_match(pattern))
+    }
+
+    pub fn regex_imatch(&mut self, column: Column<T>, pattern: &str) -> TypedTableSchema<T> {
+        self.filter(column.regex_imatch(pattern))
+    }
+
+    pub fn fts(
+        &mut self,
+        column: Column<T>,
+        query: &str,
+        config: Option<&str>,
+    ) -> TypedTableSchema<T> {
+        self.filter(column.fts(query, config))
+    }
+
+    pub fn plfts(
+        &mut self,
+        column: Column<T>,
+        query: &str,
+        config: Option<&str>,
+    ) -> TypedTableSchema<T> {
+        self.filter(column.plfts(query, config))
+    }
+
+    pub fn phfts(
+        &mut self,
+        column: Column<T>,
+        query: &str,
+        config: Option<&str>,
+    ) -> TypedTableSchema<T> {
+        self.filter(column.phfts(query, config))
+    }
+
+    pub fn wfts(
+        &mut self,
+        column: Column<T>,
+        query: &str,
+        config: Option<&str>,
+    ) -> TypedTableSchema<T> {
+        self.filter(column.wfts(query, config))
+    }
+
+    // an eq filter for every column and value pair
+    pub fn matches<I, V>(&mut self, values: I) -> TypedTableSchema<T>
+    where
+        I: IntoIterator<Item = (Column<T>, V)>,
+        V: Into<Value>,
+    {
+        for (column, value) in values {
+            self.filter(column.eq(value));
+        }
+        self.clone()
+    }
+
+    pub fn not(&mut self, filter: TableFilter<T>) -> TypedTableSchema<T> {
+        self.filter(!filter)
+    }
+
+    pub fn or<I>(&mut self, filters: I) -> TypedTableSchema<T>
+    where
+        I: IntoIterator<Item = TableFilter<T>>,
+    {
+        self.filter(TableFilter::or(filters))
+    }
+
+    pub fn and<I>(&mut self, filters: I) -> TypedTableSchema<T>
+    where
+        I: IntoIterator<Item = TableFilter<T>>,
+    {
+        self.filter(TableFilter::and(filters))
+    }
+
+    pub fn order(
+        &mut self,
+        column: Column<T>,
+        ascending: bool,
+        nulls_first: bool,
+    ) -> TypedTableSchema<T> {
+        self.inner.order(column.name, ascending, nulls_first);
+        self.clone()
+    }
+
+    // embedded resources are other tables, so these stay untyped
+    pub fn order_referenced(
+        &mut self,
+        table: &str,
+        column: &str,
+        ascending: bool,
+        nulls_first: bool,
+    ) -> TypedTableSchema<T> {
+        self.inner
+            .order_referenced(table, column, ascending, nulls_first);
+        self.clone()
+    }
+
+    pub fn filter_referenced(&mut self, table: &str, filter: Filter) -> TypedTableSchema<T> {
+        self.inner.filter_referenced(table, filter);
+        self.clone()
+    }
+
+    pub fn limit_referenced(&mut self, table: &str, count: u64) -> TypedTableSchema<T> {
+        self.inner.limit_referenced(table, count);
+        self.clone()
+    }
+
+    pub fn offset_referenced(&mut self, table: &str, count: u64) -> TypedTableSchema<T> {
+        self.inner.offset_referenced(table, count);
+        self.clone()
+    }
+
+    pub fn limit(&mut self, count: u64) -> TypedTableSchema<T> {
+        self.inner.limit(count);
+        self.clone()
+    }
+
+    pub fn offset(&mut self, count: u64) -> TypedTableSchema<T> {
+        self.inner.offset(count);
+        self.clone()
+    }
+
+    // rows `from` to `to`, both inclusive and starting at 0
+    pub fn range(&mut self, from: u64, to: u64) -> TypedTableSchema<T> {
+        self.inner.range(from, to);
+        self.clone()
+    }
+
+    pub fn count(&mut self, count: CountMode) -> TypedTableSchema<T> {
+        self.inner.count(count);
+        self.clone()
+    }
+
+    pub fn head(&mut self) -> TypedTableSchema<T> {
+        self.inner.head();
+        self.clone()
+    }
+
+    pub fn insert(&mut self, row: &T::Insert) -> TypedTableSchema<T> {
+        let data = self.serialize(row);
+        self.inner.insert(data);
+        self.clone()
+    }
+
+    pub fn insert_many(&mut self, rows: &[T::Insert]) -> TypedTableSchema<T> {
+        let data = self.serialize(rows);
+        self.inner.insert(data);
+        self.clone()
+    }
+
+    pub fn upsert(&mut self, row: &T::Insert) -> TypedTableSchema<T> {
+        let data = self.serialize(row);
+        self.inner.upsert(data);
+        self.clone()
+    }
+
+    pub fn upsert_many(&mut self, rows: &[T::Insert]) -> TypedTableSchema<T> {
+        let data = self.serialize(rows);
+        self.inner.upsert(data);
+        self.clone()
+    }
+
+    // the columns of the unique constraint an upsert resolves on
+    pub fn on_conflict(&mut self, columns: &[Column<T>]) -> TypedTableSchema<T> {
+        let columns = columns.iter().map(|c| c.name).collect::<Vec<_>>();
+        self.inner.on_conflict(&columns.join(","));
+        self.clone()
+    }
+
+    pub fn ignore_duplicates(&mut self) -> TypedTableSchema<T> {
+        self.inner.ignore_duplicates();
+        self.clone()
+    }
+
+    pub fn default_to_null(&mut self, default_to_null: bool) -> TypedTableSchema<T> {
+        self.inner.default_to_null(default_to_null);
+        self.clone()
+    }
+
+    pub fn returning(&mut self, returning: Returning) -> TypedTableSchema<T> {
+        self.inner.returning(returning);
+        self.clone()
+    }
+
+    pub fn delete(&mut self) -> TypedTableSchema<T> {
+        self.inner.delete();
+        self.clone()
+    }
+
+    pub fn update(&mut self, row: &T::Update) -> TypedTableSchema<T> {
+        let data = self.serialize(row);
+        self.inner.update(data);
+        self.clone()
+    }
+
+    pub fn idempotent(&mut self) -> TypedTableSchema<T> {
+        self.inner.idempotent();
+        self.clone()
+    }
+
+    pub async fn execute<R>(&self) -> Result<Response<R>, Error>
+    where
+        R: DeserializeOwned,
+    {
+        self.check()?;
+        self.inner.execute().await
+    }
+
+    pub async fn single<R>(&self) -> Result<Response<R>, Error>
+    where
+        R: DeserializeOwned,
+    {
+        self.check()?;
+        self.inner.single().await
+    }
+
+    pub async fn maybe_single<R>(&self) -> Result<Response<R>, Error>
+    where
+        R: DeserializeOwned,
+    {
+        self.check()?;
+        self.inner.maybe_single().await
+    }
+
+    pub async fn csv(&self) -> Result<Response<String>, Error> {
+        self.check()?;
+        self.inner.csv().await
+    }
+
+    pub async fn geojson<R>(&self) -> Result<Response<R>, Error>
+    where
+        R: DeserializeOwned,
+    {
+        self.check()?;
+        self.inner.geojson().await
+    }
+
+    pub async fn explain(&self, options: ExplainOptions) -> Result<Response<String>, Error> {
+        self.check()?;
+        self.inner.explain(options).await
+    }
+
+    fn serialize<S: Serialize + ?Sized>(&mut self, data: &S) -> Value {
+        serde_json::to_value(data).unwrap_or_else(|e| {
+            self.error = Some(e.to_string());
+            Value::Null
+        })
+    }
+
+    fn check(&self) -> Result<(), Error> {
+        match &self.error {
+            Some(message) => Err(Error::InvalidArgument {
+                message: message.clone(),
+            }),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,194 @@
+// a tiny http/1.1 server for tests, one request per connection, answering
+// with whatever the handler returns and recording every request it saw
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use base64::{Engine, engine::general_purpose};
+use serde_json::Value;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{client::SupabaseClient, retry::RetryPolicy};
+
+#[derive(Debug, Clone)]
+pub(crate) struct Request {
+    pub(crate) method: String,
+    // path and query, as sent
+    pub(crate) path: String,
+    // lowercased names
+    pub(crate) headers: HashMap<String, String>,
+    pub(crate) body: String,
+}
+
+impl Request {
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(String::as_str)
+    }
+}
+
+pub(crate) struct Reply {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl Reply {
+    pub(crate) fn status(status: u16) -> Self {
+        Self {
+            status,
+            headers: vec![],
+            body: String::new(),
+        }
+    }
+
+    pub(crate) fn json(status: u16, body: Value) -> Self {
+        Self::status(status)
+            .header("Content-Type", "application/json")
+            .body(&body.to_string())
+    }
+
+    pub(crate) fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub(crate) fn body(self, body: &str) -> Self {
+        Self {
+            body: body.to_string(),
+            ..self
+        }
+    }
+}
+
+pub(crate) struct Server {
+    pub(crate) url: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl Server {
+    pub(crate) async fn start<F>(handler: F) -> Server
+    where
+        F: Fn(&Request) -> Reply + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(vec![]));
+
+        let handler = Arc::new(handler);
+        let seen = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                let handler = handler.clone();
+                let seen = seen.clone();
+                tokio::spawn(async move { serve(socket, handler.as_ref(), &seen).await });
+            }
+        });
+
+        Server { url, requests }
+    }
+
+    // a client for this server that retries quickly
+    pub(crate) fn client(&self) -> SupabaseClient {
+        SupabaseClient::new()
+            .base_url(&self.url)
+            .api_key("anon")
+            .retry_policy(RetryPolicy::default().base_delay(Duration::from_millis(10)))
+    }
+
+    pub(crate) fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    // requests whose path starts with `prefix`
+    pub(crate) fn count(&self, prefix: &str) -> usize {
+        self.requests()
+            .iter()
+            .filter(|r| r.path.starts_with(prefix))
+            .count()
+    }
+}
+
+async fn serve(
+    mut socket: TcpStream,
+    handler: &(dyn Fn(&Request) -> Reply + Send + Sync),
+    seen: &Mutex<Vec<Request>>,
+) {
+    let Some(request) = read_request(&mut socket).await else {
+        return;
+    };
+    let reply = handler(&request);
+    seen.lock().unwrap().push(request);
+
+    let mut out = format!("HTTP/1.1 {} Stub\r\n", reply.status);
+    for (name, value) in &reply.headers {
+        out += &format!("{}: {}\r\n", name, value);
+    }
+    out += &format!(
+        "Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        reply.body.len(),
+        reply.body
+    );
+    let _ = socket.write_all(out.as_bytes()).await;
+}
+
+// reads a whole request, with a content-length or chunked body
+async fn read_request(socket: &mut TcpStream) -> Option<Request> {
+    let mut buf = vec![];
+    let mut chunk = [0u8; 4096];
+    loop {
+        let n = socket.read(&mut chunk).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+
+        let text = String::from_utf8_lossy(&buf).to_string();
+        let Some(end) = text.find("\r\n\r\n") else {
+            continue;
+        };
+
+        let mut lines = text[..end].lines();
+        let mut start = lines.next()?.split_whitespace();
+        let method = start.next()?.to_string();
+        let path = start.next()?.trim_start_matches('/').to_string();
+        let headers = lines
+            .filter_map(|l| l.split_once(':'))
+            .map(|(k, v)| (k.trim().to_ascii_lowercase(), v.trim().to_string()))
+            .collect::<HashMap<_, _>>();
+
+        let body = &buf[end + 4..];
+        let done = if let Some(len) = headers.get("content-length") {
+            body.len() >= len.parse::<usize>().ok()?
+        } else if headers
+            .get("transfer-encoding")
+            .is_some_and(|t| t == "chunked")
+        {
+            body.ends_with(b"0\r\n\r\n")
+        } else {
+            true
+        };
+        if done {
+            return Some(Request {
+                method,
+                path,
+                headers,
+                body: String::from_utf8_lossy(body).to_string(),
+            });
+        }
+    }
+}
+
+// an unsigned jwt carrying the given claims
+pub(crate) fn jwt(claims: Value) -> String {
+    let part = |v: Value| general_purpose::URL_SAFE_NO_PAD.encode(v.to_string());
+    format!(
+        "{}.{}.sig",
+        part(serde_json::json!({ "alg": "HS256" })),
+        part(claims)
+    )
+}
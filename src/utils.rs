@@ -1,11 +1,56 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use base64::{Engine, engine::general_purpose};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+
+use crate::mfa::AuthenticationMethod;
 
+// every claim decodes on its own, so an unexpected shape in one of them (say
+// an rfc 8176 string `amr`) never loses `exp` and with it token refresh
 #[derive(Debug, Serialize, Deserialize)]
 pub(crate) struct Claims {
+    #[serde(default, deserialize_with = "lenient")]
     pub(crate) exp: Option<i64>, // Expiration time as Unix timestamp
+    #[serde(default, deserialize_with = "lenient")]
     sub: Option<String>,
+    #[serde(default, deserialize_with = "lenient")]
     iat: Option<i64>,
+    // authenticator assurance level, "aal1" or "aal2"
+    #[serde(default, deserialize_with = "lenient")]
+    pub(crate) aal: Option<String>,
+    #[serde(default)]
+    amr: Option<Value>,
+}
+
+impl Claims {
+    // supabase sends `{ method, timestamp }` objects, other issuers plain
+    // method names, which get a timestamp of 0
+    pub(crate) fn authentication_methods(&self) -> Vec<AuthenticationMethod> {
+        let Some(Value::Array(entries)) = &self.amr else {
+            return vec![];
+        };
+
+        entries
+            .iter()
+            .filter_map(|entry| match entry {
+                Value::String(method) => Some(AuthenticationMethod {
+                    method: method.clone(),
+                    timestamp: 0,
+                }),
+                entry => serde_json::from_value(entry.clone()).ok(),
+            })
+            .collect()
+    }
+}
+
+fn lenient<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    let value = Value::deserialize(deserializer)?;
+    Ok(serde_json::from_value(value).ok())
 }
 
 pub(crate) fn decode_jwt(token: &str) -> Result<Claims, Box<dyn std::error::Error>> {
@@ -30,5 +75,63 @@ fn base64_decode(input: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
         _ => input.to_string(),
     };
 
-    Ok(general_purpose::STANDARD.decode(padded)?)
+    Ok(general_purpose::URL_SAFE.decode(padded)?)
+}
+
+pub(crate) fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+// percent-encodes everything but unreserved characters, so values can carry
+// `&`, `=`, `+` or `%` through the query string
+pub(crate) fn encode(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                out.push(byte as char)
+            }
+            byte => out.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::jwt;
+
+    #[test]
+    fn exp_survives_unexpected_claims() {
+        let cases = [
+            serde_json::json!({"exp": 100, "amr": [{"method": "totp", "timestamp": 5}]}),
+            serde_json::json!({"exp": 100, "amr": ["pwd", "mfa"]}),
+            serde_json::json!({"exp": 100, "amr": "pwd", "aal": 2, "sub": 7}),
+            serde_json::json!({"exp": 100, "amr": [{"method": 1}]}),
+        ];
+
+        for claims in cases {
+            let decoded = decode_jwt(&jwt(claims.clone())).unwrap();
+            assert_eq!(decoded.exp, Some(100), "{}", claims);
+        }
+    }
+
+    #[test]
+    fn reads_both_amr_forms() {
+        let decoded = decode_jwt(&jwt(serde_json::json!({
+            "amr": [{"method": "totp", "timestamp": 5}, "pwd", 3]
+        })))
+        .unwrap();
+
+        let methods = decoded
+            .authentication_methods()
+            .into_iter()
+            .map(|m| (m.method, m.timestamp))
+            .collect::<Vec<_>>();
+        assert_eq!(methods, [("totp".to_string(), 5), ("pwd".to_string(), 0)]);
+    }
 }
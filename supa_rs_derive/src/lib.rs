@@ -0,0 +1,198 @@
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::{format_ident, quote};
+use syn::{Data, DeriveInput, Fields, Ident, LitStr, Type, ext::IdentExt, parse_macro_input};
+
+// #[derive(SupabaseTable)]
+// #[supabase(table = "todos")]
+// struct Todo {
+//     #[supabase(default)]
+//     id: i64,
+//     #[supabase(column = "is_done")]
+//     done: bool,
+// }
+//
+// implements `supa_rs::table::Table`, adds a `Column` constant per field
+// (`Todo::done`) and generates `TodoInsert` and `TodoUpdate`. the table
+// defaults to the snake_case struct name and columns to the field names
+#[proc_macro_derive(SupabaseTable, attributes(supabase))]
+pub fn derive_supabase_table(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+struct Column {
+    ident: Ident,
+    ty: Type,
+    name: String,
+    // filled in by the database, so optional when inserting
+    default: bool,
+}
+
+fn expand(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "SupabaseTable does not support generic structs",
+        ));
+    }
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "SupabaseTable needs a struct with named fields",
+                ));
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "SupabaseTable can only be derived for structs",
+            ));
+        }
+    };
+
+    let mut table = snake_case(&input.ident.to_string());
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("supabase")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("table") {
+                table = meta.value()?.parse::<LitStr>()?.value();
+                Ok(())
+            } else {
+                Err(meta.error("expected `table = \"...\"`"))
+            }
+        })?;
+    }
+
+    let mut columns = vec![];
+    for field in fields {
+        let ident = field.ident.clone().expect("named field");
+        let mut column = Column {
+            name: ident.unraw().to_string(),
+            ident,
+            ty: field.ty.clone(),
+            default: false,
+        };
+
+        for attr in field.attrs.iter().filter(|a| a.path().is_ident("supabase")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("column") {
+                    column.name = meta.value()?.parse::<LitStr>()?.value();
+                    Ok(())
+                } else if meta.path.is_ident("default") {
+                    column.default = true;
+                    Ok(())
+                } else {
+                    Err(meta.error("expected `column = \"...\"` or `default`"))
+                }
+            })?;
+        }
+
+        columns.push(column);
+    }
+
+    let vis = &input.vis;
+    let ident = &input.ident;
+    let insert = format_ident!("{}Insert", ident);
+    let update = format_ident!("{}Update", ident);
+
+    let constants = columns.iter().map(|c| {
+        let field = &c.ident;
+        let name = LitStr::new(&c.name, Span::call_site());
+        quote! {
+            #vis const #field: ::supa_rs::table::Column<#ident> =
+                ::supa_rs::table::Column::new(#name);
+        }
+    });
+
+    let insert_fields = columns.iter().map(|c| {
+        let field = &c.ident;
+        let name = LitStr::new(&c.name, Span::call_site());
+        let ty = &c.ty;
+        if is_option(ty) {
+            quote! {
+                #[serde(rename = #name, skip_serializing_if = "::core::option::Option::is_none")]
+                pub #field: #ty
+            }
+        } else if c.default {
+            quote! {
+                #[serde(rename = #name, skip_serializing_if = "::core::option::Option::is_none")]
+                pub #field: ::core::option::Option<#ty>
+            }
+        } else {
+            quote! {
+                #[serde(rename = #name)]
+                pub #field: #ty
+            }
+        }
+    });
+
+    // nullable columns become `Option<Option<T>>` so they can be set to null
+    let update_fields = columns.iter().map(|c| {
+        let field = &c.ident;
+        let name = LitStr::new(&c.name, Span::call_site());
+        let ty = &c.ty;
+        quote! {
+            #[serde(rename = #name, skip_serializing_if = "::core::option::Option::is_none")]
+            pub #field: ::core::option::Option<#ty>
+        }
+    });
+
+    let table = LitStr::new(&table, Span::call_site());
+
+    Ok(quote! {
+        impl ::supa_rs::table::Table for #ident {
+            const NAME: &'static str = #table;
+            type Insert = #insert;
+            type Update = #update;
+        }
+
+        #[allow(non_upper_case_globals)]
+        impl #ident {
+            #(#constants)*
+        }
+
+        #[derive(Debug, Clone, ::supa_rs::serde::Serialize)]
+        #[serde(crate = "::supa_rs::serde")]
+        #vis struct #insert {
+            #(#insert_fields,)*
+        }
+
+        #[derive(Debug, Clone, Default, ::supa_rs::serde::Serialize)]
+        #[serde(crate = "::supa_rs::serde")]
+        #vis struct #update {
+            #(#update_fields,)*
+        }
+    })
+}
+
+fn is_option(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) => path
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "Option"),
+        _ => false,
+    }
+}
+
+fn snake_case(name: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 {
+                out.push('_');
+            }
+            out.extend(c.to_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
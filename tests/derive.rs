@@ -0,0 +1,114 @@
+#![cfg(feature = "derive")]
+
+use serde_json::json;
+use supa_rs::SupabaseTable;
+use supa_rs::client::SupabaseClient;
+use supa_rs::table::Table;
+
+#[allow(dead_code)]
+#[derive(SupabaseTable)]
+#[supabase(table = "todo_items")]
+struct TodoItem {
+    #[supabase(default)]
+    id: i64,
+    #[supabase(column = "is_done")]
+    done: bool,
+    r#type: String,
+    note: Option<String>,
+    #[supabase(default)]
+    created_at: String,
+}
+
+#[allow(dead_code)]
+#[derive(SupabaseTable)]
+struct UserProfile {
+    id: i64,
+}
+
+#[test]
+fn names_tables_and_columns() {
+    assert_eq!(TodoItem::NAME, "todo_items");
+    assert_eq!(UserProfile::NAME, "user_profile");
+
+    assert_eq!(TodoItem::id.name(), "id");
+    assert_eq!(TodoItem::done.name(), "is_done");
+    assert_eq!(TodoItem::r#type.name(), "type");
+}
+
+#[test]
+fn insert_leaves_out_defaulted_and_null_columns() {
+    let row = TodoItemInsert {
+        id: None,
+        done: true,
+        r#type: "chore".to_string(),
+        note: None,
+        created_at: None,
+    };
+    assert_eq!(
+        serde_json::to_value(&row).unwrap(),
+        json!({ "is_done": true, "type": "chore" })
+    );
+
+    let row = TodoItemInsert {
+        id: Some(7),
+        note: Some("soon".to_string()),
+        ..row
+    };
+    assert_eq!(
+        serde_json::to_value(&row).unwrap(),
+        json!({ "id": 7, "is_done": true, "type": "chore", "note": "soon" })
+    );
+}
+
+#[test]
+fn update_only_sends_set_columns() {
+    let cases = [
+        (TodoItemUpdate::default(), json!({})),
+        (
+            TodoItemUpdate {
+                done: Some(false),
+                ..Default::default()
+            },
+            json!({ "is_done": false }),
+        ),
+        // a nullable column is set to null with `Some(None)`
+        (
+            TodoItemUpdate {
+                note: Some(None),
+                ..Default::default()
+            },
+            json!({ "note": null }),
+        ),
+        (
+            TodoItemUpdate {
+                r#type: Some("errand".to_string()),
+                note: Some(Some("later".to_string())),
+                ..Default::default()
+            },
+            json!({ "type": "errand", "note": "later" }),
+        ),
+    ];
+
+    for (row, expected) in cases {
+        assert_eq!(serde_json::to_value(&row).unwrap(), expected);
+    }
+}
+
+#[test]
+fn typed_queries_use_column_names() {
+    let client = SupabaseClient::new();
+    let query = client
+        .from_table::<TodoItem>()
+        .on_conflict(&[TodoItem::id, TodoItem::r#type])
+        .update(&TodoItemUpdate {
+            done: Some(true),
+            ..Default::default()
+        });
+
+    assert_eq!(query.inner.name, "todo_items");
+    assert_eq!(
+        query.inner.params,
+        [("on_conflict".to_string(), "id,type".to_string())]
+    );
+    assert_eq!(query.inner.update, Some(json!({ "is_done": true })));
+}